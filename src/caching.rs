@@ -1 +1,2 @@
 pub mod cal_caching;
+pub mod refresh;
@@ -1,194 +1,680 @@
 use chrono::{prelude::*, Duration};
 use icalendar::Calendar;
-use std::sync::Arc;
-use std::{collections::HashMap, error::Error, fs, io::BufReader};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::calendar_parsing::rooms::{canonical_selection, Room};
+use crate::networking::ade_api_handling::TimeWindow;
+
+/// The two kinds of cached calendars: the ones downloaded from ADE, and the ones computed from them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Layer {
+    Raw,
+    Computed,
+}
 
-use crate::calendar_parsing::rooms::EnseirbRoom;
+impl Layer {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Layer::Raw => "raw",
+            Layer::Computed => "computed",
+        }
+    }
+}
 
-// try to save the data to the file
-// if successful, update the resource last updated time
-// otherwise, return an error
-pub fn save_resource_to_cache_file(res_id: u16, data: String) -> Result<(), Box<dyn Error>> {
-    let file_name = format!("cache/{res_id}.ics");
-    fs::write(file_name, data)?;
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct EntryKey {
+    layer: Layer,
+    site: String,
+    key: String,
+}
 
-    update_resource_last_update_time(res_id)?;
-    Ok(())
+impl EntryKey {
+    // every entry but the last calendar downloaded for each room
+    fn is_evictable(&self) -> bool {
+        !(self.layer == Layer::Raw && self.key.ends_with(LAST_RESOURCE_SUFFIX))
+    }
 }
 
-// only returns the resource if it was updated less than 60 minutes ago
-pub fn get_resource_from_cache_file(res_id: u16) -> Option<String> {
-    let current_time: DateTime<Utc> = Utc::now();
-    let update_time: DateTime<Utc> = match get_resource_last_update_time(res_id) {
-        Ok(_update_time) => _update_time,
-        Err(_) => DateTime::from_timestamp(0, 0)?,
-    }; // default to Jan 1 1970, which SHOULD be longer ago than whatever max time we set
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    data: Arc<str>,
+    updated_at: DateTime<Utc>,
+    last_used: DateTime<Utc>,
+}
 
-    let ret_val;
+/// How long the cached calendars stay fresh, and how much room they can take
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    raw_ttl: Duration,
+    computed_ttl: Duration,
+    // in bytes, None if there is no limit
+    max_size: Option<u64>,
+}
 
-    if current_time - update_time <= Duration::minutes(60) {
-        let file_name = format!("cache/{res_id}.ics");
-        match fs::read_to_string(file_name) {
-            Ok(_str) => ret_val = Some(_str),
-            Err(_) => ret_val = None,
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            raw_ttl: Duration::minutes(60),
+            computed_ttl: Duration::minutes(60),
+            max_size: None,
         }
-    } else {
-        ret_val = None;
     }
-
-    ret_val
 }
 
-pub fn init_resource_last_update_time() -> Result<(), Box<dyn Error>> {
-    match fs::create_dir("cache") {
-        Ok(_) => {}
-        Err(e) => match e.kind() {
-            // Fail on any error except already exists, bc the dir probably already does exist
-            std::io::ErrorKind::AlreadyExists => {}
-            _ => {
-                return Err(e)?;
-            }
-        },
-    };
-    let mut hm = HashMap::new();
-    let res_ids: [u16; 25] = [
-        3224, 3223, 3222, 3260, 3259, 3258, 3254, 3253, 3252, 3251, 3250, 3249, 3248, 3247, 3280,
-        3230, 3296, 3329, 3330, 3331, 3327, 3314, 3315, 3316, 3318,
-    ];
-    for res_id in res_ids {
-        hm.insert(res_id, 0i64);
+impl CacheConfig {
+    /// `max_size` is in bytes, `None` if there is no limit
+    ///
+    /// ```
+    /// use ade::{CacheConfig, CalendarCache};
+    /// use chrono::Duration;
+    ///
+    /// let config = CacheConfig::new(Duration::minutes(10), Duration::minutes(30), Some(50 << 20));
+    /// let cache = CalendarCache::in_memory(config);
+    /// ```
+    pub fn new(raw_ttl: Duration, computed_ttl: Duration, max_size: Option<u64>) -> Self {
+        Self {
+            raw_ttl,
+            computed_ttl,
+            max_size,
+        }
     }
 
-    let file = fs::File::create("cache/update_times.json")?;
+    fn ttl(&self, layer: Layer) -> Duration {
+        match layer {
+            Layer::Raw => self.raw_ttl,
+            Layer::Computed => self.computed_ttl,
+        }
+    }
+}
 
-    Ok(serde_json::to_writer(file, &hm)?)
+/// The cached calendars, shared by the request handlers and the background refresh.
+/// When it has a directory, every entry is also written there (atomically), but only to
+/// survive a restart: the cache is never read from disk after startup.
+#[derive(Debug, Default)]
+pub struct CalendarCache {
+    entries: Mutex<HashMap<EntryKey, CacheEntry>>,
+    dir: Option<PathBuf>,
+    config: CacheConfig,
+    // makes the names of the temporary files unique, in case two tasks save the same entry
+    writes: AtomicU64,
 }
 
-fn get_resource_last_update_time(res_id: u16) -> Result<DateTime<Utc>, Box<dyn Error>> {
-    let file = fs::File::open("cache/update_times.json")?;
-    let reader = BufReader::new(file);
+impl CalendarCache {
+    pub fn in_memory(config: CacheConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the entries saved in `dir` by a previous run (their age is the age of their file),
+    /// and saves the new ones there
+    pub fn persistent(dir: &Path, config: CacheConfig) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("couldn't create the cache directory {}: {e}", dir.display()))?;
 
-    let data: HashMap<u16, i64> = serde_json::from_reader(reader)?;
+        let mut entries: HashMap<EntryKey, CacheEntry> = HashMap::new();
 
-    let date = match data.get(&res_id) {
-        Some(_date) => _date,
-        None => &0,
+        for site in fs::read_dir(dir)? {
+            let site = site?;
+            if !site.file_type()?.is_dir() {
+                continue;
+            }
+
+            for layer in [Layer::Raw, Layer::Computed] {
+                let Ok(files) = fs::read_dir(site.path().join(layer.dir_name())) else {
+                    continue;
+                };
+
+                for file in files.flatten() {
+                    let path = file.path();
+                    let Some(key) = path
+                        .file_name()
+                        .and_then(|x| x.to_str())
+                        .and_then(|x| x.strip_suffix(".ics"))
+                    else {
+                        // leftovers of an interrupted write
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    };
+
+                    let (Ok(data), Ok(modified)) = (
+                        fs::read_to_string(&path),
+                        file.metadata().and_then(|x| x.modified()),
+                    ) else {
+                        tracing::warn!("Skipping unreadable cache file {}", path.display());
+                        continue;
+                    };
+
+                    entries.insert(
+                        EntryKey {
+                            layer,
+                            site: site.file_name().to_string_lossy().to_string(),
+                            key: key.to_string(),
+                        },
+                        CacheEntry {
+                            data: data.into(),
+                            updated_at: modified.into(),
+                            last_used: modified.into(),
+                        },
+                    );
+                }
+            }
+        }
+
+        tracing::info!(
+            "Loaded {} cached calendars from {}",
+            entries.len(),
+            dir.display()
+        );
+
+        let cache = Self {
+            entries: Mutex::new(entries),
+            dir: Some(dir.to_path_buf()),
+            config,
+            writes: AtomicU64::new(0),
+        };
+        // the limit may have been lowered since the last run
+        cache.evict();
+
+        Ok(cache)
     }
-    .to_owned();
 
-    match DateTime::from_timestamp(date, 0) {
-        Some(_ret_val) => Ok(_ret_val),
-        None => Err("Failed to transform timestamp to date".to_string())?,
+    fn file_path(&self, key: &EntryKey) -> Option<PathBuf> {
+        Some(
+            self.dir
+                .as_ref()?
+                .join(&key.site)
+                .join(key.layer.dir_name())
+                .join(format!("{}.ics", key.key)),
+        )
     }
-}
 
-fn update_resource_last_update_time(res_id: u16) -> Result<(), Box<dyn Error>> {
-    let file = fs::File::open("cache/update_times.json")?;
-    let reader = BufReader::new(file);
+    fn get(&self, layer: Layer, site: &str, key: &str) -> Option<CacheEntry> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
 
-    let mut data: HashMap<u16, i64> = serde_json::from_reader(reader)?;
+        let entry = entries.get_mut(&EntryKey {
+            layer,
+            site: site.to_string(),
+            key: key.to_string(),
+        })?;
+        entry.last_used = Utc::now();
 
-    data.insert(res_id, Utc::now().timestamp());
+        Some(entry.clone())
+    }
 
-    let file = fs::File::create("cache/update_times.json")?;
+    // only returns the entry if it was updated less than the TTL of its layer ago
+    fn get_fresh(&self, layer: Layer, site: &str, key: &str) -> Option<Arc<str>> {
+        self.get(layer, site, key)
+            .filter(|x| Utc::now() - x.updated_at <= self.config.ttl(layer))
+            .map(|x| x.data)
+    }
 
-    Ok(serde_json::to_writer(file, &data)?)
-}
+    // the entry is available as soon as it's in memory, failing to save it to disk is only logged
+    fn insert(&self, layer: Layer, site: &str, key: &str, data: String) {
+        let entry_key = EntryKey {
+            layer,
+            site: site.to_string(),
+            key: key.to_string(),
+        };
+        if let Some(path) = self.file_path(&entry_key) {
+            if let Err(e) = self.save_to_disk(&path, &data) {
+                tracing::warn!("Failed to save {key} to the cache directory: {e}");
+            }
+        }
+
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                entry_key,
+                CacheEntry {
+                    data: data.into(),
+                    updated_at: Utc::now(),
+                    last_used: Utc::now(),
+                },
+            );
+
+        self.evict();
+    }
+
+    // drops the expired calendars, then the ones nobody asked for in the longest time until the
+    // cache fits in its maximum size. the last calendar downloaded for each room is always kept:
+    // it is all there is to answer with when ADE is down
+    fn evict(&self) {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut evicted: Vec<EntryKey> = entries
+            .iter()
+            .filter(|(key, entry)| {
+                key.is_evictable() && now - entry.updated_at > self.config.ttl(key.layer)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &evicted {
+            entries.remove(key);
+        }
+
+        if let Some(max_size) = self.config.max_size {
+            let mut size: u64 = entries.values().map(|x| x.data.len() as u64).sum();
+
+            let mut evictable: Vec<(DateTime<Utc>, EntryKey)> = entries
+                .iter()
+                .filter(|(key, _)| key.is_evictable())
+                .map(|(key, entry)| (entry.last_used, key.clone()))
+                .collect();
+            evictable.sort_by_key(|(last_used, _)| *last_used);
+
+            for (_, key) in evictable {
+                if size <= max_size {
+                    break;
+                }
+                if let Some(entry) = entries.remove(&key) {
+                    size -= entry.data.len() as u64;
+                    evicted.push(key);
+                }
+            }
 
-pub fn get_cached_free_rooms_cal(cal_list: Arc<Vec<EnseirbRoom>>) -> Option<Calendar> // returns cached calendar if it exists and is recent enough, otherwise returns none
-{
-    let current_time: DateTime<Utc> = Utc::now();
-    let update_time: DateTime<Utc> = match get_cal_last_update_time(cal_list.clone()) {
-        Ok(_update_time) => _update_time,
-        Err(_) => DateTime::from_timestamp(0, 0)?,
-    }; // default to Jan 1 1970, which SHOULD be longer ago than whatever max time we set
+            if size > max_size {
+                tracing::warn!(
+                    "The last calendars of the rooms alone don't fit in the maximum cache size"
+                );
+            }
+        }
+        drop(entries);
 
-    let ret_val;
+        if !evicted.is_empty() {
+            tracing::info!("Evicted {} calendars from the cache", evicted.len());
+        }
 
-    if current_time - update_time <= Duration::minutes(60) {
-        let file_name = format!("cache/{}.ics", room_list_to_filename(cal_list));
-        match fs::read_to_string(file_name) {
-            Ok(_str) => ret_val = _str,
-            Err(_) => return None,
+        for path in evicted.iter().filter_map(|x| self.file_path(x)) {
+            let _ = fs::remove_file(path);
         }
-    } else {
-        return None;
     }
 
-    match ret_val.parse::<Calendar>() {
-        Ok(_cal) => Some(_cal.into()),
-        Err(_) => None,
+    // writes to a temporary file first, so that a crash never leaves a truncated calendar behind
+    fn save_to_disk(&self, path: &Path, data: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let write = self.writes.fetch_add(1, Ordering::Relaxed);
+        let temp_file = path.with_extension(format!("{write}.tmp"));
+        fs::write(&temp_file, data)?;
+        fs::rename(&temp_file, path)?;
+
+        Ok(())
     }
-}
 
-pub fn cache_free_rooms_cal(
-    cal_list: Arc<Vec<EnseirbRoom>>,
-    value: &Calendar,
-) -> Result<(), Box<dyn Error>> // TODO: caches the value to a file corresponding to the provided calendar list
-{
-    let file_name = format!("cache/{}.ics", room_list_to_filename(cal_list.clone()));
-    let data = format!("{}", value);
-    fs::write(file_name, data)?;
+    // also kept as the last good calendar of the room, whatever its window
+    pub fn save_resource(&self, site: &str, res_id: u32, window: TimeWindow, data: String) {
+        self.insert(Layer::Raw, site, &last_resource_key(res_id), data.clone());
+        self.insert(Layer::Raw, site, &resource_cache_key(res_id, window), data);
+    }
 
-    update_cal_last_update_time(cal_list)?;
-    Ok(())
-}
+    // only returns the resource if it was updated less than the raw TTL ago
+    pub fn get_resource(&self, site: &str, res_id: u32, window: TimeWindow) -> Option<String> {
+        self.get_fresh(Layer::Raw, site, &resource_cache_key(res_id, window))
+            .map(|x| x.to_string())
+    }
 
-pub fn init_cal_last_update_time() -> Result<(), Box<dyn Error>> {
-    match fs::create_dir("cache") {
-        Ok(_) => {}
-        Err(e) => match e.kind() {
-            // Fail on any error except already exists, bc the dir probably already does exist
-            std::io::ErrorKind::AlreadyExists => {}
-            _ => {
-                return Err(e)?;
-            }
-        },
-    };
-    let hm: HashMap<String, i64> = HashMap::new();
+    // returns the last calendar saved for the resource, whatever its age and window, along with
+    // the time it was saved, for when ADE can't be reached. the window of the request usually
+    // moves every day, so its events have to be clipped to it. returns none if it was never saved
+    pub fn get_stale_resource(&self, site: &str, res_id: u32) -> Option<(String, DateTime<Utc>)> {
+        self.get(Layer::Raw, site, &last_resource_key(res_id))
+            .map(|x| (x.data.to_string(), x.updated_at))
+    }
+
+    // returns the cached computed data (a calendar, or JSON) if it exists and is recent enough
+    pub fn get_computed(&self, site: &str, key: &str) -> Option<String> {
+        self.get_fresh(Layer::Computed, site, key)
+            .map(|x| x.to_string())
+    }
+
+    pub fn cache_computed(&self, site: &str, key: &str, data: String) {
+        self.insert(Layer::Computed, site, key, data);
+    }
+
+    // returns the cached computed calendar if it exists and is recent enough, otherwise returns none
+    pub fn get_cal(&self, site: &str, key: &str) -> Option<Calendar> {
+        self.get_fresh(Layer::Computed, site, key)?
+            .parse::<Calendar>()
+            .ok()
+    }
+
+    pub fn cache_cal(&self, site: &str, key: &str, value: &Calendar) {
+        self.cache_computed(site, key, format!("{}", value));
+    }
+
+    pub fn get_free_rooms_cal(
+        &self,
+        site: &str,
+        cal_list: Arc<Vec<Room>>,
+        window: TimeWindow,
+    ) -> Option<Calendar> {
+        self.get_cal(site, &room_list_to_filename(cal_list, window))
+    }
 
-    let file = fs::File::create("cache/cal_update_times.json")?;
+    pub fn cache_free_rooms_cal(
+        &self,
+        site: &str,
+        cal_list: Arc<Vec<Room>>,
+        window: TimeWindow,
+        value: &Calendar,
+    ) {
+        self.cache_cal(site, &room_list_to_filename(cal_list, window), value)
+    }
+}
 
-    Ok(serde_json::to_writer(file, &hm)?)
+pub fn zik_cal_key(window: TimeWindow) -> String {
+    format!("zik_{}", window.cache_key())
 }
 
-fn update_cal_last_update_time(res_id: Arc<Vec<EnseirbRoom>>) -> Result<(), Box<dyn Error>> {
-    let file = fs::File::open("cache/cal_update_times.json")?;
-    let reader = BufReader::new(file);
+// the levels of the Zik calendar of the window, as JSON
+pub fn zik_slots_key(window: TimeWindow) -> String {
+    format!("zik_slots_{}", window.cache_key())
+}
 
-    let mut data: HashMap<String, i64> = serde_json::from_reader(reader)?;
+// FNV-1a: unlike the hasher of the standard library, it gives the same hash in every run
+// and with every version of Rust, which matters since the keys are file names
+fn stable_hash(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
 
-    data.insert(room_list_to_filename(res_id), Utc::now().timestamp());
+// the same rooms give the same key whatever their order, and the key stays short however many rooms there are
+fn room_list_to_filename(res_id: Arc<Vec<Room>>, window: TimeWindow) -> String {
+    let rooms = canonical_selection(res_id.iter().cloned());
+    let hash = stable_hash(rooms.iter().flat_map(|x| x.id().to_le_bytes()));
 
-    let file = fs::File::create("cache/cal_update_times.json")?;
+    format!("rooms_{hash:016x}_{}", window.cache_key())
+}
 
-    Ok(serde_json::to_writer(file, &data)?)
+fn resource_cache_key(res_id: u32, window: TimeWindow) -> String {
+    format!("{res_id}_{}", window.cache_key())
 }
 
-fn room_list_to_filename(res_id: Arc<Vec<EnseirbRoom>>) -> String {
-    res_id
-        .iter()
-        .map(|x| x.short_name())
-        .collect::<Vec<String>>()
-        .join("")
+const LAST_RESOURCE_SUFFIX: &str = "_last";
+
+fn last_resource_key(res_id: u32) -> String {
+    format!("{res_id}{LAST_RESOURCE_SUFFIX}")
 }
 
-fn get_cal_last_update_time(
-    res_id: Arc<Vec<EnseirbRoom>>,
-) -> Result<DateTime<Utc>, Box<dyn Error>> {
-    let file = fs::File::open("cache/cal_update_times.json")?;
-    let reader = BufReader::new(file);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    fn window() -> TimeWindow {
+        TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 1).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ade-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn entries_survive_a_restart() {
+        let dir = temp_dir("restart");
+
+        let cache = CalendarCache::persistent(&dir, CacheConfig::default()).unwrap();
+        cache.save_resource("enseirb", 3258, window(), "BEGIN:VCALENDAR".to_string());
+        drop(cache);
+
+        let cache = CalendarCache::persistent(&dir, CacheConfig::default()).unwrap();
+        assert_eq!(
+            cache.get_resource("enseirb", 3258, window()).as_deref(),
+            Some("BEGIN:VCALENDAR")
+        );
+        // the layers and the sites don't share their entries
+        assert!(cache.get_resource("other", 3258, window()).is_none());
+        assert!(cache
+            .get_fresh(
+                Layer::Computed,
+                "enseirb",
+                &resource_cache_key(3258, window())
+            )
+            .is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn old_entries_are_only_served_as_stale() {
+        let cache = CalendarCache::in_memory(CacheConfig::default());
+        cache.save_resource("enseirb", 3258, window(), "BEGIN:VCALENDAR".to_string());
+
+        let updated_at = Utc::now() - Duration::minutes(61);
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|x| x.updated_at = updated_at);
+
+        assert!(cache.get_resource("enseirb", 3258, window()).is_none());
+        assert_eq!(
+            cache.get_stale_resource("enseirb", 3258),
+            Some(("BEGIN:VCALENDAR".to_string(), updated_at))
+        );
+    }
+
+    #[test]
+    fn each_layer_has_its_own_ttl() {
+        let cache = CalendarCache::in_memory(CacheConfig {
+            raw_ttl: Duration::minutes(10),
+            computed_ttl: Duration::minutes(120),
+            max_size: None,
+        });
+        cache.save_resource("enseirb", 3258, window(), "BEGIN:VCALENDAR".to_string());
+        cache.cache_cal("enseirb", &zik_cal_key(window()), &Calendar::new().done());
+
+        let updated_at = Utc::now() - Duration::minutes(30);
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|x| x.updated_at = updated_at);
+
+        assert!(cache.get_resource("enseirb", 3258, window()).is_none());
+        assert!(cache.get_cal("enseirb", &zik_cal_key(window())).is_some());
+    }
+
+    #[test]
+    fn least_recently_requested_calendars_are_evicted_first() {
+        let dir = temp_dir("eviction");
+        let calendar = Calendar::new().done();
+        let size = calendar.to_string().len() as u64;
+        // room for the raw calendar (saved for its window and as the last one of the room)
+        // and two computed ones
+        let config = CacheConfig {
+            max_size: Some(4 * size),
+            ..CacheConfig::default()
+        };
+        let cache = CalendarCache::persistent(&dir, config).unwrap();
+
+        cache.save_resource("enseirb", 3258, window(), calendar.to_string());
+        cache.cache_cal("enseirb", "a", &calendar);
+        cache.cache_cal("enseirb", "b", &calendar);
+        // "a" and the raw calendar are requested again after "b" was computed
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(cache.get_cal("enseirb", "a").is_some());
+        assert!(cache.get_resource("enseirb", 3258, window()).is_some());
+        cache.cache_cal("enseirb", "c", &calendar);
+
+        assert!(cache.get_cal("enseirb", "a").is_some());
+        assert!(cache.get_cal("enseirb", "b").is_none());
+        assert!(cache.get_cal("enseirb", "c").is_some());
+        assert!(!dir.join("enseirb/computed/b.ics").exists());
+        assert!(cache.get_resource("enseirb", 3258, window()).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_calendars_of_past_windows_dont_pile_up() {
+        let dir = temp_dir("windows");
+        let calendar = "BEGIN:VCALENDAR".to_string();
+        let size = calendar.len() as u64;
+        let config = CacheConfig {
+            max_size: Some(4 * size),
+            ..CacheConfig::default()
+        };
+        let cache = CalendarCache::persistent(&dir, config).unwrap();
+
+        // a long running server asked for a new window every day, and by many clients
+        let first_day = NaiveDate::from_ymd_opt(2024, 10, 21).unwrap();
+        let windows: Vec<TimeWindow> = (0..100)
+            .map(|x| TimeWindow::from_days(first_day + Duration::days(x), 1).unwrap())
+            .collect();
+        for window in &windows {
+            cache.save_resource("enseirb", 3258, *window, calendar.clone());
+        }
+
+        let entries = cache.entries.lock().unwrap().clone();
+        assert!(entries.values().map(|x| x.data.len() as u64).sum::<u64>() <= 4 * size);
+        assert!(fs::read_dir(dir.join("enseirb/raw")).unwrap().count() <= 4);
+        // the last window and the last calendar of the room are kept
+        assert!(cache.get_resource("enseirb", 3258, windows[99]).is_some());
+        assert!(cache.get_resource("enseirb", 3258, windows[0]).is_none());
+        assert!(cache.get_stale_resource("enseirb", 3258).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_calendars_are_dropped_but_the_last_one_of_each_room() {
+        let cache = CalendarCache::in_memory(CacheConfig::default());
+        cache.save_resource("enseirb", 3258, window(), "BEGIN:VCALENDAR".to_string());
+        cache.cache_cal("enseirb", &zik_cal_key(window()), &Calendar::new().done());
+
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .for_each(|x| x.updated_at = Utc::now() - Duration::days(2));
+        // even without a maximum size, the next insertion sweeps the expired entries
+        let tomorrow = TimeWindow::from_days(window().last_day() + Duration::days(1), 1).unwrap();
+        cache.save_resource("enseirb", 3259, tomorrow, "BEGIN:VCALENDAR".to_string());
+
+        let keys: Vec<String> = cache
+            .entries
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|x| x.key.clone())
+            .sorted();
+        assert_eq!(
+            keys,
+            [
+                "3258_last".to_string(),
+                resource_cache_key(3259, tomorrow),
+                "3259_last".to_string()
+            ]
+        );
+    }
 
-    let data: HashMap<String, i64> = serde_json::from_reader(reader)?;
+    fn permutations(rooms: &[Room]) -> Vec<Vec<Room>> {
+        if rooms.is_empty() {
+            return vec![Vec::new()];
+        }
 
-    let date = match data.get(room_list_to_filename(res_id).as_str()) {
-        Some(_date) => _date,
-        None => &0,
+        (0..rooms.len())
+            .flat_map(|i| {
+                let mut others = rooms.to_vec();
+                let first = others.remove(i);
+                permutations(&others).into_iter().map(move |mut x| {
+                    x.insert(0, first.clone());
+                    x
+                })
+            })
+            .collect()
     }
-    .to_owned();
 
-    match DateTime::from_timestamp(date, 0) {
-        Some(_ret_val) => Ok(_ret_val),
-        None => Err("Failed to transform timestamp to date".to_string())?,
+    #[test]
+    fn room_selections_hit_the_same_entry_in_any_order() {
+        let registry = crate::calendar_parsing::rooms::RoomRegistry::from_toml(include_str!(
+            "../../rooms.toml"
+        ))
+        .unwrap();
+        let site = registry.default_site();
+        let rooms: Vec<Room> = ["TD04", "TD05", "TD06", "TD17"]
+            .iter()
+            .map(|x| site.from_string(x.to_string()).unwrap())
+            .collect();
+
+        let cache = CalendarCache::in_memory(CacheConfig::default());
+        cache.cache_free_rooms_cal(
+            "enseirb",
+            Arc::new(rooms.clone()),
+            window(),
+            &Calendar::new().done(),
+        );
+
+        for permutation in permutations(&rooms) {
+            assert!(cache
+                .get_free_rooms_cal("enseirb", Arc::new(permutation), window())
+                .is_some());
+        }
+        // duplicates don't matter either
+        let duplicated: Vec<Room> = rooms.iter().chain(rooms.iter().rev()).cloned().collect();
+        assert!(cache
+            .get_free_rooms_cal("enseirb", Arc::new(duplicated), window())
+            .is_some());
+
+        // but another selection is another entry
+        assert!(cache
+            .get_free_rooms_cal("enseirb", Arc::new(rooms[1..].to_vec()), window())
+            .is_none());
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_writes_are_not_lost() {
+        let dir = temp_dir("concurrent");
+        let cache = Arc::new(CalendarCache::persistent(&dir, CacheConfig::default()).unwrap());
+
+        let threads: Vec<_> = (0..8)
+            .map(|id| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        cache.save_resource("enseirb", id, window(), format!("calendar {id}"));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let cache = CalendarCache::persistent(&dir, CacheConfig::default()).unwrap();
+        for id in 0..8 {
+            assert_eq!(
+                cache.get_resource("enseirb", id, window()),
+                Some(format!("calendar {id}"))
+            );
+        }
+        // no temporary file is left behind, only the calendar of each room for its window and
+        // as the last one of the room
+        assert_eq!(fs::read_dir(dir.join("enseirb/raw")).unwrap().count(), 16);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
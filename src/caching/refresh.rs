@@ -0,0 +1,201 @@
+use chrono::{prelude::*, Duration};
+use std::sync::Arc;
+
+use crate::{
+    calendar_parsing::{
+        parsing::{compute_free_rooms_calendar, compute_zik_calendar},
+        rooms::{RoomRegistry, Site},
+        zik_policy::ZikPolicy,
+    },
+    free_rooms::{AppData, ServerConfig},
+    networking::ade_api_handling::{refresh_calendar_list, TimeWindow},
+    networking::calendar_source::CalendarStore,
+};
+
+/// When the room calendars are refreshed in the background: often during working hours
+/// (in the timezone of the default site, Monday to Friday), rarely at night and during weekends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RefreshSchedule {
+    interval: Duration,
+    idle_interval: Duration,
+    start_hour: u32,
+    end_hour: u32,
+}
+
+impl RefreshSchedule {
+    /// The intervals are in minutes, the idle one is never shorter than the other.
+    /// Returns None if the interval is 0, which disables the background refresh.
+    pub fn new(interval: u32, idle_interval: u32, start_hour: u32, end_hour: u32) -> Option<Self> {
+        if interval == 0 {
+            return None;
+        }
+
+        Some(Self {
+            interval: Duration::minutes(i64::from(interval)),
+            idle_interval: Duration::minutes(i64::from(idle_interval.max(interval))),
+            start_hour: start_hour.min(24),
+            end_hour: end_hour.min(24),
+        })
+    }
+
+    fn is_working_time(&self, now: NaiveDateTime) -> bool {
+        now.weekday().num_days_from_monday() < 5
+            && (self.start_hour..self.end_hour).contains(&now.hour())
+    }
+
+    // the start of the next working hours after now, if there are any working hours at all
+    fn next_working_time(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = NaiveTime::from_hms_opt(self.start_hour, 0, 0)?;
+
+        now.date()
+            .iter_days()
+            .take(8)
+            .map(|x| x.and_time(start))
+            .find(|x| *x > now && self.is_working_time(*x))
+    }
+
+    /// How long to wait before the next refresh. Outside working hours, the wait never goes
+    /// past the start of the working hours, so that the first requests of the day are warm.
+    pub fn next_delay(&self, now: NaiveDateTime) -> Duration {
+        if self.is_working_time(now) {
+            return self.interval;
+        }
+
+        match self.next_working_time(now) {
+            Some(next) => self.idle_interval.min(next - now),
+            None => self.idle_interval,
+        }
+    }
+}
+
+// refreshes the calendar of every room of every site, and precomputes the calendars
+// of the requests without parameters (all the rooms of the site, default time window)
+async fn refresh_all(
+    store: Arc<CalendarStore>,
+    registry: &RoomRegistry,
+    zik_policy: Arc<ZikPolicy>,
+    config: &ServerConfig,
+) {
+    // the window starts today at the site, like the requests without a start.
+    // a u16 number of days from today is far from the last date chrono can represent
+    let from_today = |site: &Site, days: u16| {
+        TimeWindow::from_days(site.today(), days)
+            .expect("the window starts today")
+            .clamp(config.max_days)
+    };
+
+    for site in registry.sites() {
+        let window = from_today(site, config.free_rooms_timespan);
+
+        match refresh_calendar_list(store.clone(), site.clone(), site.rooms(), window).await {
+            Ok(count) => tracing::info!(
+                "Refreshed {count}/{} room calendars of {}",
+                site.rooms().len(),
+                site.name()
+            ),
+            Err(e) => tracing::error!(
+                "Failed to refresh the room calendars of {}: {e}",
+                site.name()
+            ),
+        }
+
+        compute_free_rooms_calendar(store.clone(), site.clone(), site.rooms(), window).await;
+    }
+
+    let window = from_today(&zik_policy.site(), config.zik_timespan.saturating_mul(7));
+
+    match refresh_calendar_list(store.clone(), zik_policy.site(), zik_policy.rooms(), window).await
+    {
+        Ok(count) => tracing::info!(
+            "Refreshed {count}/{} room calendars of the Zik",
+            zik_policy.rooms().len()
+        ),
+        Err(e) => tracing::error!("Failed to refresh the room calendars of the Zik: {e}"),
+    }
+
+    compute_zik_calendar(store, zik_policy, window).await;
+}
+
+/// Refreshes the calendars forever, following the schedule. Meant to be spawned as a background task.
+pub async fn refresh_calendars(data: AppData, config: ServerConfig, schedule: RefreshSchedule) {
+    loop {
+        tracing::info!("Refreshing the calendars in the background...");
+        refresh_all(
+            data.store.clone(),
+            &data.registry,
+            data.zik_policy.clone(),
+            &config,
+        )
+        .await;
+
+        // the working hours are those of the default site, whatever the timezone of the host
+        let timezone = data.registry.default_site().timezone();
+        let delay = schedule.next_delay(Utc::now().with_timezone(&timezone).naive_local());
+        tracing::info!("Next refresh in {} minutes", delay.num_minutes());
+
+        // the delay is never negative
+        tokio::time::sleep(delay.to_std().unwrap_or_default()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> RefreshSchedule {
+        RefreshSchedule {
+            interval: Duration::minutes(10),
+            idle_interval: Duration::minutes(45),
+            start_hour: 7,
+            end_hour: 20,
+        }
+    }
+
+    fn at(date_time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn refreshes_often_during_working_hours() {
+        // a Monday
+        assert_eq!(
+            schedule().next_delay(at("2024-10-21 14:00")),
+            Duration::minutes(10)
+        );
+        assert_eq!(
+            schedule().next_delay(at("2024-10-21 07:00")),
+            Duration::minutes(10)
+        );
+    }
+
+    #[test]
+    fn refreshes_rarely_at_night_and_during_weekends() {
+        assert_eq!(
+            schedule().next_delay(at("2024-10-21 20:00")),
+            Duration::minutes(45)
+        );
+        // a Saturday
+        assert_eq!(
+            schedule().next_delay(at("2024-10-26 14:00")),
+            Duration::minutes(45)
+        );
+    }
+
+    #[test]
+    fn wakes_up_for_the_working_hours() {
+        assert_eq!(
+            schedule().next_delay(at("2024-10-22 06:40")),
+            Duration::minutes(20)
+        );
+        // Monday morning, after the weekend
+        assert_eq!(
+            schedule().next_delay(at("2024-10-28 06:50")),
+            Duration::minutes(10)
+        );
+        // Saturday morning isn't working time
+        assert_eq!(
+            schedule().next_delay(at("2024-10-26 06:50")),
+            Duration::minutes(45)
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub mod intervals;
 pub mod parsing;
 pub mod rooms;
+pub mod schedules;
+pub mod slots;
+pub mod zik_policy;
@@ -0,0 +1,205 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// A half-open time interval: `start` is included, `end` isn't
+///
+/// ```
+/// use ade::Interval;
+/// use chrono::{TimeZone, Utc};
+///
+/// let at = |hour| Utc.with_ymd_and_hms(2024, 10, 21, hour, 0, 0).unwrap();
+/// let morning = Interval::new(at(8), at(12)).unwrap();
+/// let lunch = Interval::new(at(12), at(14)).unwrap();
+///
+/// assert!(!morning.overlaps(&lunch));
+/// assert!(morning.contains_instant(&at(8)) && !morning.contains_instant(&at(12)));
+/// assert_eq!(morning.duration().num_hours(), 4);
+/// assert!(Interval::new(at(14), at(12)).is_none());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Interval {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Interval {
+    // returns None if the interval would end before it starts
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Option<Self> {
+        if end < start {
+            return None;
+        }
+
+        Some(Self { start, end })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    // two intervals overlap if they share at least one instant, so touching intervals
+    // and empty intervals don't
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start.max(other.start) < self.end.min(other.end)
+    }
+
+    pub fn contains(&self, other: &Interval) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    pub fn contains_instant(&self, instant: &DateTime<Utc>) -> bool {
+        &self.start <= instant && instant < &self.end
+    }
+
+    // the part of the interval that falls within the bounds, if there is one
+    pub fn clip(&self, bounds: &Interval) -> Option<Interval> {
+        let clipped = Interval {
+            start: self.start.max(bounds.start),
+            end: self.end.min(bounds.end),
+        };
+
+        if clipped.start >= clipped.end {
+            return None;
+        }
+
+        Some(clipped)
+    }
+}
+
+/// Merges overlapping or touching intervals, and returns them sorted. Empty intervals are dropped.
+pub fn merge(intervals: &[Interval]) -> Vec<Interval> {
+    let mut sorted: Vec<Interval> = intervals
+        .iter()
+        .filter(|x| !x.is_empty())
+        .copied()
+        .collect();
+    sorted.sort();
+
+    let mut merged: Vec<Interval> = Vec::new();
+    for interval in sorted {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => {
+                last.end = last.end.max(interval.end);
+            }
+            _ => merged.push(interval),
+        }
+    }
+
+    merged
+}
+
+/// Returns the parts of `from` that aren't covered by any of the intervals, sorted
+pub fn subtract(from: &Interval, intervals: &[Interval]) -> Vec<Interval> {
+    let mut remaining: Vec<Interval> = Vec::new();
+    let mut cursor = from.start;
+
+    for interval in merge(intervals) {
+        if interval.end <= cursor {
+            continue;
+        }
+        if interval.start >= from.end {
+            break;
+        }
+        if interval.start > cursor {
+            remaining.push(Interval {
+                start: cursor,
+                end: interval.start,
+            });
+        }
+        cursor = interval.end;
+    }
+
+    if cursor < from.end {
+        remaining.push(Interval {
+            start: cursor,
+            end: from.end,
+        });
+    }
+
+    remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn interval(start: i64, length: i64) -> Interval {
+        let start = DateTime::from_timestamp(start * 60, 0).unwrap();
+        Interval::new(start, start + Duration::minutes(length)).unwrap()
+    }
+
+    fn arb_interval() -> impl Strategy<Value = Interval> {
+        (0i64..1000, 0i64..200).prop_map(|(start, length)| interval(start, length))
+    }
+
+    #[test]
+    fn touching_intervals_do_not_overlap() {
+        assert!(!interval(0, 10).overlaps(&interval(10, 10)));
+        assert!(interval(0, 11).overlaps(&interval(10, 10)));
+    }
+
+    #[test]
+    fn contained_interval_overlaps() {
+        let outer = interval(0, 60);
+        let inner = interval(20, 10);
+
+        assert!(outer.overlaps(&inner));
+        assert!(inner.overlaps(&outer));
+        assert!(outer.contains(&inner));
+        assert!(!inner.contains(&outer));
+    }
+
+    proptest! {
+        #[test]
+        fn overlap_is_symmetric(a in arb_interval(), b in arb_interval()) {
+            prop_assert_eq!(a.overlaps(&b), b.overlaps(&a));
+        }
+
+        #[test]
+        fn overlap_matches_clip(a in arb_interval(), b in arb_interval()) {
+            prop_assert_eq!(a.overlaps(&b), a.clip(&b).is_some());
+        }
+
+        #[test]
+        fn merged_intervals_are_sorted_and_disjoint(
+            intervals in prop::collection::vec(arb_interval(), 0..20)
+        ) {
+            let merged = merge(&intervals);
+
+            for (a, b) in merged.iter().zip(merged.iter().skip(1)) {
+                prop_assert!(a.end < b.start);
+            }
+            for x in intervals.iter().filter(|x| !x.is_empty()) {
+                prop_assert!(merged.iter().any(|m| m.contains(x)));
+            }
+        }
+
+        #[test]
+        fn subtracted_intervals_do_not_overlap_the_removed_ones(
+            from in arb_interval(),
+            intervals in prop::collection::vec(arb_interval(), 0..20)
+        ) {
+            let remaining = subtract(&from, &intervals);
+
+            for r in &remaining {
+                prop_assert!(from.contains(r));
+                prop_assert!(!r.is_empty());
+                for x in &intervals {
+                    prop_assert!(!r.overlaps(x));
+                }
+            }
+
+            // whatever was removed is covered by one of the intervals
+            let total: Duration = remaining.iter().map(|x| x.duration()).sum();
+            let covered: Duration = merge(&intervals)
+                .iter()
+                .filter_map(|x| x.clip(&from))
+                .map(|x| x.duration())
+                .sum();
+            prop_assert_eq!(total + covered, from.duration());
+        }
+    }
+}
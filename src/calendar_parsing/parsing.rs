@@ -1,28 +1,38 @@
 use chrono::{prelude::*, Duration};
 use icalendar::{
-    Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike,
-    Property,
+    Calendar, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike, Property,
 };
 
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    caching::cal_caching::{
-        cache_free_rooms_cal, get_cached_free_rooms_cal, get_resource_from_cache_file,
+    caching::cal_caching::{zik_cal_key, zik_slots_key},
+    calendar_parsing::{
+        intervals::{subtract, Interval},
+        rooms::{Room, Site},
+        schedules::{get_room_schedules, sweep, RoomEvent, RoomSchedule},
+        slots::{BusyRoom, DataNotice, FoundRoom, FreeRoomNow, FreeRoomsSlot, RoomInfo, ZikSlot},
+        zik_policy::{ZikLevel, ZikPolicy},
+    },
+    networking::{
+        ade_api_handling::{get_free_rooms_calendar_list, TimeWindow},
+        calendar_source::CalendarStore,
     },
-    calendar_parsing::rooms::EnseirbRoom,
-    networking::ade_api_handling::get_free_rooms_calendar_list,
 };
 
 const MAX_CALS_TOGETHER: usize = 3;
 
 use std::sync::Arc;
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum WindowPosition {
     Open,
     Closed,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum AllowedActivities {
     QuietPlaying(WindowPosition),
     LoudPlaying(WindowPosition),
@@ -30,6 +40,29 @@ pub enum AllowedActivities {
 }
 
 impl AllowedActivities {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            AllowedActivities::QuietPlaying(WindowPosition::Closed) => {
+                "Faible volume, fenêtre fermée."
+            }
+            AllowedActivities::QuietPlaying(WindowPosition::Open) => {
+                "Faible volume, fenêtre ouverte."
+            }
+            AllowedActivities::LoudPlaying(WindowPosition::Closed) => {
+                "Volume élevé, fenêtre fermée."
+            }
+            AllowedActivities::LoudPlaying(WindowPosition::Open) => {
+                "Volume élevé, fenêtre ouverte."
+            }
+            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Closed) => {
+                "Volume élevé et batterie, fenêtre fermée."
+            }
+            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open) => {
+                "Volume élevé et batterie, fenêtre ouverte."
+            }
+        }
+    }
+
     fn enum_index(&self) -> u8 {
         match *self {
             AllowedActivities::QuietPlaying(WindowPosition::Closed) => 1,
@@ -48,7 +81,7 @@ impl Ord for AllowedActivities {
 }
 impl PartialOrd for AllowedActivities {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(&other))
+        Some(self.cmp(other))
     }
 }
 impl PartialEq for AllowedActivities {
@@ -62,67 +95,19 @@ impl Eq for AllowedActivities {}
 // FUNCTIONS
 // ------------------------------------------------------------------------------------------------
 
-fn parse_cal_to_cut_times(cal: Calendar) -> Vec<DateTime<Utc>> {
-    let mut cut_times: Vec<DateTime<Utc>> = Vec::new();
-
-    for component in &cal.components {
-        if let CalendarComponent::Event(event) = component {
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(time)) = match event.get_start()
-            {
-                Some(start_) => start_,
-                None => {
-                    tracing::warn!("Failed to parse event start time");
-                    continue;
-                }
-            } {
-                cut_times.push(time);
-            };
-
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(time)) = match event.get_end() {
-                Some(end_) => end_,
-                None => {
-                    tracing::warn!("Failed to parse event end time");
-                    continue;
-                }
-            } {
-                cut_times.push(time);
-            }
-        }
-    }
-
-    cut_times
-}
-
-async fn get_cut_times(calendar_list: Arc<Vec<EnseirbRoom>>) -> Vec<DateTime<Utc>> {
-    let mut cut_times: Vec<DateTime<Utc>> = Vec::new();
-
-    let cal_list;
-
-    cal_list = match get_free_rooms_calendar_list(calendar_list).await {
-        Ok(_list) => _list,
-        Err(_) => {
-            tracing::error!("Failed to get calendar cut times");
-            return cut_times;
-        }
-    };
-
-    for calendar_file in cal_list.iter() {
-        let cal: Calendar = match calendar_file.parse() {
-            Ok(cal_) => cal_,
-            Err(_) => {
-                tracing::warn!("Failed to parse calendar file, skipping...");
-                continue;
-            }
-        };
-        cut_times.extend(parse_cal_to_cut_times(cal));
-    }
+fn get_cut_times(schedules: &[RoomSchedule]) -> Vec<DateTime<Utc>> {
+    let mut cut_times: Vec<DateTime<Utc>> = schedules
+        .iter()
+        .flat_map(|x| x.events.iter())
+        .flat_map(|x| [x.start, x.end])
+        .collect();
 
     // sort the times and remove duplicate times
     cut_times.sort();
     cut_times.dedup();
 
     // sanity check before we take the first element
-    if cut_times.len() <= 0 {
+    if cut_times.is_empty() {
         return cut_times;
     }
 
@@ -145,82 +130,224 @@ async fn get_cut_times(calendar_list: Arc<Vec<EnseirbRoom>>) -> Vec<DateTime<Utc
     cut_times
 }
 
+fn get_slots(schedules: &[RoomSchedule]) -> Vec<Interval> {
+    get_cut_times(schedules)
+        .into_iter()
+        .tuple_windows()
+        .map(|(start, end)| Interval { start, end })
+        .collect()
+}
+
+// a room is busy during the slot as soon as one of its events overlaps it.
+// missing rooms are neither free nor busy, they are only listed in the notice
 fn get_free_rooms(
-    start_time: &DateTime<Utc>,
-    end_time: &DateTime<Utc>,
-    calendar_list: Arc<Vec<EnseirbRoom>>,
-) -> String {
-    let mut free_rooms: Vec<String> = calendar_list.iter().filter_map(|x| x.name()).collect();
+    schedules: &[RoomSchedule],
+    slot: &Interval,
+    overlapping_events: &[(usize, &RoomEvent)],
+) -> FreeRoomsSlot {
+    let mut courses: Vec<Vec<String>> = vec![Vec::new(); schedules.len()];
+
+    for (index, event) in overlapping_events {
+        courses[*index].push(event.summary.clone());
+    }
+
+    let mut free_rooms: Vec<RoomInfo> = Vec::new();
+    let mut busy_rooms: Vec<BusyRoom> = Vec::new();
 
-    for calendar_file in calendar_list
+    for (schedule, courses) in schedules.iter().zip(courses) {
+        if schedule.is_missing() {
+            continue;
+        }
+
+        if courses.is_empty() {
+            free_rooms.push((&schedule.room).into());
+        } else {
+            busy_rooms.push(BusyRoom {
+                room: (&schedule.room).into(),
+                courses,
+            });
+        }
+    }
+
+    FreeRoomsSlot {
+        start: slot.start,
+        end: slot.end,
+        free_rooms,
+        busy_rooms,
+    }
+}
+
+/// Cuts time at the start and end of every event, and lists the free and busy rooms of each slot
+///
+/// ```
+/// use ade::{compute_free_rooms_slots, RoomRegistry, RoomSchedule};
+///
+/// let registry = RoomRegistry::from_toml(
+///     r#"
+///     [[sites]]
+///     name = "enseirb"
+///     ade_url = "https://ade.example.com"
+///     project_id = 1
+///     display_config_id = 71
+///     rooms = [
+///         { short_name = "TD06", id = 3258 },
+///         { short_name = "TD07", id = 3257 },
+///     ]
+///     "#,
+/// )?;
+/// let site = registry.default_site();
+/// let calendar = |start: &str, end: &str| {
+///     format!(
+///         "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\n\
+///          DTSTART:{start}\r\nDTEND:{end}\r\nSUMMARY:Analyse\r\n\
+///          END:VEVENT\r\nEND:VCALENDAR\r\n"
+///     )
+/// };
+///
+/// let schedules = [
+///     RoomSchedule::parse(
+///         site.from_string("TD06".to_string()).unwrap(),
+///         &calendar("20241021T080000Z", "20241021T100000Z"),
+///         site.timezone(),
+///     ),
+///     RoomSchedule::parse(
+///         site.from_string("TD07".to_string()).unwrap(),
+///         &calendar("20241021T090000Z", "20241021T120000Z"),
+///         site.timezone(),
+///     ),
+/// ];
+///
+/// // 8:00-9:00, 9:00-10:00 and 10:00-12:00
+/// let slots = compute_free_rooms_slots(&schedules);
+/// assert_eq!(slots.len(), 3);
+/// assert_eq!(slots[0].free_rooms[0].short_name, "TD07");
+/// assert!(slots[1].free_rooms.is_empty());
+/// assert_eq!(slots[2].free_rooms[0].short_name, "TD06");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn compute_free_rooms_slots(schedules: &[RoomSchedule]) -> Vec<FreeRoomsSlot> {
+    sweep(
+        schedules,
+        &get_slots(schedules),
+        |slot, overlapping_events| get_free_rooms(schedules, slot, overlapping_events),
+    )
+}
+
+// the notice lists the stale and missing rooms, even when there are no slots
+pub async fn get_free_rooms_slots(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> (Vec<FreeRoomsSlot>, DataNotice) {
+    let schedules = get_room_schedules(store, site, calendar_list, window).await;
+
+    (
+        compute_free_rooms_slots(&schedules),
+        DataNotice::from_schedules(&schedules),
+    )
+}
+
+// a room is free at the given time if none of its events is going on (returns None otherwise),
+// and it stays free until the start of its next event (if there is one in the fetched window)
+fn get_room_free_until(
+    schedule: &RoomSchedule,
+    at: &DateTime<Utc>,
+) -> Option<Option<DateTime<Utc>>> {
+    if schedule
+        .events
         .iter()
-        .filter_map(|x| x.id())
-        .filter_map(|x| get_resource_from_cache_file(x))
+        .any(|x| x.interval().contains_instant(at))
     {
-        let cal: Calendar = match calendar_file.parse() {
-            Ok(cal_) => cal_,
-            Err(_) => {
-                tracing::warn!("Failed to parse calendar file. Skipping...");
-                continue;
-            }
-        };
+        return None;
+    }
 
-        for component in &cal.components {
-            let start: DateTime<Utc>;
-            let end: DateTime<Utc>;
-            let event: &Event;
+    Some(
+        schedule
+            .events
+            .iter()
+            .map(|x| x.start)
+            .find(|start| start > at),
+    )
+}
 
-            if let CalendarComponent::Event(evt) = component {
-                event = evt;
-            } else {
-                continue;
-            }
+// missing rooms are never free, they are only listed in the notice
+pub async fn get_rooms_free_at(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+    at: DateTime<Utc>,
+) -> (Vec<FreeRoomNow>, DataNotice) {
+    let schedules = get_room_schedules(store, site, calendar_list, window).await;
+
+    let mut free_rooms: Vec<FreeRoomNow> = schedules
+        .iter()
+        .filter(|schedule| !schedule.is_missing())
+        .filter_map(|schedule| {
+            let free_until = get_room_free_until(schedule, &at)?;
+
+            Some(FreeRoomNow {
+                room: (&schedule.room).into(),
+                free_until,
+                free_minutes: free_until.map(|x| (x - at).num_minutes()),
+                distance: None,
+            })
+        })
+        .collect();
 
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(event_start_time)) =
-                match event.get_start() {
-                    Some(start_) => start_,
-                    None => {
-                        tracing::warn!("Failed to parse event start time. Skipping...");
-                        continue;
-                    } // we simply skip the iteration if we're unable to parse
-                }
-            {
-                start = event_start_time;
-            } else {
-                continue;
-            }
+    // the rooms that stay free the longest come first
+    free_rooms.sort_by_key(|x| std::cmp::Reverse(x.free_until.unwrap_or(DateTime::<Utc>::MAX_UTC)));
 
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(event_end_time)) =
-                match event.get_end() {
-                    Some(end_) => end_,
-                    None => {
-                        tracing::warn!("Failed to parse event end time. Skipping...");
-                        continue;
-                    } // we simply skip the iteration if we're unable to parse
-                }
-            {
-                end = event_end_time;
-            } else {
-                continue;
-            }
+    (free_rooms, DataNotice::from_schedules(&schedules))
+}
 
-            let loc = match event.get_location() {
-                Some(loc_) => loc_,
-                None => {
-                    tracing::warn!("Failed to parse event location. Skipping...");
-                    continue;
-                } // we simply skip the iteration if we're unable to parse
-            };
-
-            if (&start <= start_time && start_time < &end)
-                || (&start < end_time && end_time <= &end)
-            {
-                free_rooms.retain(|value| *value != loc);
-            }
-        }
-    }
+// returns the free stretch of a room (within the window) that contains the wanted interval,
+// or None if the room is busy at some point of the interval
+fn get_room_free_stretch(
+    schedule: &RoomSchedule,
+    window: &Interval,
+    wanted: &Interval,
+) -> Option<Interval> {
+    let events: Vec<Interval> = schedule.events.iter().map(|x| x.interval()).collect();
+
+    subtract(window, &events)
+        .into_iter()
+        .find(|x| x.contains(wanted))
+}
+
+// missing rooms are never found, they are only listed in the notice
+pub async fn find_free_rooms(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+    wanted: Interval,
+) -> (Vec<FoundRoom>, DataNotice) {
+    let timezone = site.timezone();
+    let schedules = get_room_schedules(store, site, calendar_list, window).await;
+    let window = window.interval(timezone);
+
+    let mut found_rooms: Vec<FoundRoom> = schedules
+        .iter()
+        .filter(|schedule| !schedule.is_missing())
+        .filter_map(|schedule| {
+            let stretch = get_room_free_stretch(schedule, &window, &wanted)?;
+
+            // the bounds of the window aren't events
+            Some(FoundRoom {
+                room: (&schedule.room).into(),
+                free_from: (stretch.start > window.start).then_some(stretch.start),
+                free_until: (stretch.end < window.end).then_some(stretch.end),
+                free_minutes: stretch.duration().num_minutes(),
+            })
+        })
+        .collect();
+
+    // the rooms with the most free time around the interval come first
+    found_rooms.sort_by_key(|x| std::cmp::Reverse(x.free_minutes));
 
-    free_rooms.join(", ")
+    (found_rooms, DataNotice::from_schedules(&schedules))
 }
 
 fn init_ade_cal() -> Calendar {
@@ -234,14 +361,50 @@ fn init_ade_cal() -> Calendar {
     cal
 }
 
-fn show_cals_together(calendar_list: Arc<Vec<EnseirbRoom>>) -> Calendar {
+// an all-day event covering the whole window, so that the rooms whose data is stale or missing
+// show up at the top of every day instead of looking free
+fn notice_event(notice: &DataNotice, window: TimeWindow) -> Option<Event> {
+    if notice.is_empty() {
+        return None;
+    }
+
+    Some(
+        Event::new()
+            .description(&notice.description())
+            .starts(DatePerhapsTime::Date(window.first_day()))
+            .ends(DatePerhapsTime::Date(window.last_day() + Duration::days(1)))
+            .summary("Données ADE incomplètes")
+            .last_modified(Local::now().into())
+            .created(DateTime::from_timestamp_nanos(0))
+            .sequence(2141946518)
+            .done(),
+    )
+}
+
+async fn show_cals_together(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Calendar {
     let mut outcal = init_ade_cal();
 
-    for calendar_file in calendar_list
-        .iter()
-        .filter_map(|x| x.id())
-        .filter_map(|x| get_resource_from_cache_file(x))
-    {
+    let cal_list = match get_free_rooms_calendar_list(store, site, calendar_list, window).await {
+        Ok(_list) => _list,
+        Err(_) => {
+            tracing::error!("Failed to get the room calendars");
+            Vec::new()
+        }
+    };
+
+    let mut notice = DataNotice::default();
+
+    for calendar in cal_list {
+        notice.add(&calendar.room, calendar.status);
+
+        let Some(calendar_file) = calendar.data else {
+            continue;
+        };
         let mut appended: Calendar = match calendar_file.parse() {
             Ok(_cal) => _cal,
             Err(_) => {
@@ -252,39 +415,37 @@ fn show_cals_together(calendar_list: Arc<Vec<EnseirbRoom>>) -> Calendar {
         outcal.append(&mut appended);
     }
 
-    outcal
-}
-
-pub async fn get_free_rooms_calendar(calendar_list: Arc<Vec<EnseirbRoom>>) -> Calendar {
-    match get_cached_free_rooms_cal(calendar_list.clone()) {
-        Some(cal) => return cal,
-        None => {
-            tracing::info!("Cache miss, downloading and parsing free rooms...")
-        }
+    if let Some(event) = notice_event(&notice, window) {
+        outcal.push(event);
     }
 
-    let tmp: Vec<DateTime<Utc>>;
-
-    tmp = get_cut_times(calendar_list.clone()).await;
-
-    // NOTE: HAS to be after tmp's creation so we're sure to get a cache hit
-    if calendar_list.len() <= MAX_CALS_TOGETHER {
-        return show_cals_together(calendar_list);
-    }
+    outcal
+}
 
+/// Renders the free rooms as an iCalendar calendar, with an event per slot listing the free rooms.
+/// Rooms without a display name aren't listed, like in ADE.
+pub fn free_rooms_slots_to_calendar(
+    slots: &[FreeRoomsSlot],
+    notice: &DataNotice,
+    window: TimeWindow,
+) -> Calendar {
     let mut cal = init_ade_cal();
 
-    let cut_times: Vec<(DateTime<Utc>, DateTime<Utc>)> = tmp.into_iter().tuple_windows().collect();
-
-    // dbg!(&cut_times);
+    if let Some(event) = notice_event(notice, window) {
+        cal.push(event);
+    }
 
-    for (start_time, end_time) in cut_times.iter() {
-        let free_rooms = get_free_rooms(start_time, end_time, calendar_list.clone());
-        let start = DatePerhapsTime::DateTime(CalendarDateTime::Utc(start_time.clone()));
-        let end = DatePerhapsTime::DateTime(CalendarDateTime::Utc(end_time.clone()));
+    for slot in slots {
+        let free_rooms = slot.free_room_names();
+        let description = ["Salles Libres:".to_string()]
+            .into_iter()
+            .chain(slot.free_room_details())
+            .join("\n");
+        let start = DatePerhapsTime::DateTime(CalendarDateTime::Utc(slot.start));
+        let end = DatePerhapsTime::DateTime(CalendarDateTime::Utc(slot.end));
         cal.push(
             Event::new()
-                .description("Salles Libres:")
+                .description(&description)
                 .location(&free_rooms)
                 .starts(start)
                 .ends(end)
@@ -295,202 +456,204 @@ pub async fn get_free_rooms_calendar(calendar_list: Arc<Vec<EnseirbRoom>>) -> Ca
         );
     }
 
-    let cal_final = cal.done();
+    cal.done()
+}
+
+pub async fn get_free_rooms_calendar(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Calendar {
+    match store
+        .cache
+        .get_free_rooms_cal(&site.name(), calendar_list.clone(), window)
+    {
+        Some(cal) => return cal,
+        None => {
+            tracing::info!("Cache miss, downloading and parsing free rooms...")
+        }
+    }
+
+    compute_free_rooms_calendar(store, site, calendar_list, window).await
+}
+
+// computes the calendar from the room calendars (cached or not) and caches it, unless some of them
+// are stale or missing: the calendar is computed again as soon as ADE answers
+pub async fn compute_free_rooms_calendar(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Calendar {
+    if calendar_list.len() <= MAX_CALS_TOGETHER {
+        return show_cals_together(store, site, calendar_list, window).await;
+    }
+
+    let schedules =
+        get_room_schedules(store.clone(), site.clone(), calendar_list.clone(), window).await;
+    let notice = DataNotice::from_schedules(&schedules);
+
+    let cal_final =
+        free_rooms_slots_to_calendar(&compute_free_rooms_slots(&schedules), &notice, window);
 
-    let _ = cache_free_rooms_cal(calendar_list, &cal_final); // TODO: logging
+    if notice.is_empty() {
+        store
+            .cache
+            .cache_free_rooms_cal(&site.name(), calendar_list, window, &cal_final);
+    }
 
     cal_final
 }
 
-fn get_allowed_level(
-    start_time: &DateTime<Utc>,
-    end_time: &DateTime<Utc>,
-    calendar_list: Arc<Vec<EnseirbRoom>>,
-) -> AllowedActivities {
-    let mut allowed_level: AllowedActivities =
-        AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open);
-
-    for calendar_file in calendar_list.iter().filter_map(|x| x.name()) {
-        let cal: Calendar = match calendar_file.parse() {
-            Ok(cal_) => cal_,
-            Err(_) => {
-                tracing::warn!("Failed to parse calendar file. Skipping iteration...");
-                continue;
-            }
-        };
+/// The level allowed in the Zik during each slot of the schedules. Rooms whose calendar is
+/// missing never lower the level here, unlike in the Zik calendar, which considers them busy.
+pub fn compute_zik_levels<'a>(
+    policy: &ZikPolicy,
+    schedules: &'a [RoomSchedule],
+) -> Vec<(Interval, ZikLevel<'a>)> {
+    sweep(
+        schedules,
+        &get_slots(schedules),
+        |slot, overlapping_events| (*slot, policy.level(schedules, slot, overlapping_events)),
+    )
+}
 
-        for component in &cal.components {
-            let start: DateTime<Utc>;
-            let end: DateTime<Utc>;
-            let event: &Event;
+// the exams are listed after the level, along with the rooms they take place in
+fn zik_description(level: &ZikLevel) -> String {
+    let activity = level.allowed_activities.description();
+    let exams = level.exam_names();
 
-            if let CalendarComponent::Event(evt) = component {
-                event = evt;
-            } else {
-                continue;
-            }
+    if exams.is_empty() {
+        activity.to_string()
+    } else {
+        format!("{activity}\nExamen en cours : {exams}")
+    }
+}
 
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(event_start_time)) =
-                match event.get_start() {
-                    Some(start_) => start_,
-                    None => {
-                        tracing::warn!("Failed to parse event start time. Skipping...");
-                        continue;
-                    } // we simply skip the iteration if we're unable to parse
-                }
-            {
-                start = event_start_time;
-            } else {
-                continue;
-            }
+pub async fn get_zik_calendar(
+    store: Arc<CalendarStore>,
+    policy: Arc<ZikPolicy>,
+    window: TimeWindow,
+) -> Calendar {
+    match store
+        .cache
+        .get_cal(&policy.site().name(), &zik_cal_key(window))
+    {
+        Some(cal) => return cal,
+        None => {
+            tracing::info!("Cache miss, downloading and parsing the Zik calendar...")
+        }
+    }
 
-            if let DatePerhapsTime::DateTime(CalendarDateTime::Utc(event_end_time)) =
-                match event.get_end() {
-                    Some(end_) => end_,
-                    None => {
-                        tracing::warn!("Failed to parse event end time. Skipping...");
-                        continue;
-                    } // we simply skip the iteration if we're unable to parse
-                }
-            {
-                end = event_end_time;
-            } else {
-                continue;
-            }
+    compute_zik_calendar(store, policy, window).await
+}
 
-            if (&start <= start_time && start_time < &end)
-                || (&start < end_time && end_time <= &end)
-            {
-                if let Some(location) = event.get_location() {
-                    match location {
-                        "EA-S106/S107 (TD06)" | "EA-S108/S109 (TD07)" | "EA-S008/S009 (TD17)" => {
-                            let current_allowed_level =
-                                AllowedActivities::QuietPlaying(WindowPosition::Closed);
-                            if current_allowed_level < allowed_level {
-                                allowed_level = current_allowed_level;
-                            }
-                        }
-
-                        "EA-S101/S102 (TD04)" | "EA-S104/S105 (TD05)" | "EA-S110/S111 (TD08)" => {
-                            let current_allowed_level =
-                                AllowedActivities::QuietPlaying(WindowPosition::Open);
-                            if current_allowed_level < allowed_level {
-                                allowed_level = current_allowed_level;
-                            }
-                        }
-
-                        "EA-S112/S113 (TD09)" | "EA-S114 (TD10)" | "EA-S115/S116 (TD11)" => {
-                            let current_allowed_level =
-                                AllowedActivities::LoudPlaying(WindowPosition::Closed);
-                            if current_allowed_level < allowed_level {
-                                allowed_level = current_allowed_level;
-                            }
-                        }
-
-                        "EA-S117/S118 (TD12)" | "EA-S119/S120 (TD13)" | "EA-S121/S122 (TD14)" => {
-                            let current_allowed_level =
-                                AllowedActivities::LoudPlaying(WindowPosition::Open);
-                            if current_allowed_level < allowed_level {
-                                allowed_level = current_allowed_level;
-                            }
-                        }
-
-                        "EB-P118/P119 (TD22)"
-                        | "EB-P121 (TD23)"
-                        | "EB-P123 (TD24)"
-                        | "EB-P145 (TD25)"
-                        | "EB-P147 (TD26)"
-                        | "EB-P148/P150 (TD27)"
-                        | "EB-P153/P156 (TD28)"
-                        | "EA-S225 (TD15)"
-                        | "EB-P010/P011 (TD20)"
-                        | "EB-P117 (TD21)" => {
-                            let current_allowed_level =
-                                AllowedActivities::LoudPlayingAndBattery(WindowPosition::Closed);
-                            if current_allowed_level < allowed_level {
-                                allowed_level = current_allowed_level;
-                            }
-                        }
-
-                        _ => {
-                            tracing::warn!("Unknown room encoutered. Ignoring...")
-                        }
-                    }
-                } else {
-                    continue;
-                }
-            }
+// a room whose calendar is missing could be busy at any time, so it's considered busy
+// during the whole window
+fn fill_missing_schedules(schedules: &mut [RoomSchedule], window: &Interval) {
+    for schedule in schedules.iter_mut().filter(|x| x.is_missing()) {
+        schedule.events = vec![RoomEvent {
+            start: window.start,
+            end: window.end,
+            summary: "Données indisponibles".to_string(),
+            description: None,
+            location: None,
+        }];
+    }
+}
+
+/// The schedules the levels of the Zik are computed from: only the rooms the policy has a rule
+/// for or looks for exams in are fetched, and the missing ones are busy during the whole window
+pub async fn get_zik_schedules(
+    store: Arc<CalendarStore>,
+    policy: &ZikPolicy,
+    window: TimeWindow,
+) -> (Vec<RoomSchedule>, DataNotice) {
+    let mut schedules = get_room_schedules(store, policy.site(), policy.rooms(), window).await;
+    let notice = DataNotice::from_schedules(&schedules);
+    fill_missing_schedules(&mut schedules, &window.interval(policy.site().timezone()));
+
+    (schedules, notice)
+}
+
+// the levels are computed once for the calendar and the timetables, and both are cached.
+// like the free rooms calendar, they aren't when some of the room calendars are stale or missing
+async fn compute_zik(
+    store: Arc<CalendarStore>,
+    policy: &ZikPolicy,
+    window: TimeWindow,
+) -> (Calendar, Vec<ZikSlot>, DataNotice) {
+    let (schedules, notice) = get_zik_schedules(store.clone(), policy, window).await;
+    let levels = compute_zik_levels(policy, &schedules);
+
+    let cal_final = zik_levels_to_calendar(&levels, &notice, window);
+    let slots: Vec<ZikSlot> = levels.iter().map(ZikSlot::from).collect();
+
+    if notice.is_empty() {
+        let site = policy.site().name();
+        store
+            .cache
+            .cache_cal(&site, &zik_cal_key(window), &cal_final);
+        match serde_json::to_string(&slots) {
+            Ok(json) => store
+                .cache
+                .cache_computed(&site, &zik_slots_key(window), json),
+            Err(e) => tracing::error!("Failed to serialize the Zik levels: {e}"),
         }
     }
 
-    allowed_level
+    (cal_final, slots, notice)
 }
 
-pub async fn get_zik_calendar() -> Calendar {
-    let room_list: Arc<Vec<EnseirbRoom>> = Arc::from(vec![
-        EnseirbRoom::TD01,
-        EnseirbRoom::TD02,
-        EnseirbRoom::TD03,
-        EnseirbRoom::TD04,
-        EnseirbRoom::TD05,
-        EnseirbRoom::TD06,
-        EnseirbRoom::TD07,
-        EnseirbRoom::TD08,
-        EnseirbRoom::TD09,
-        EnseirbRoom::TD10,
-        EnseirbRoom::TD11,
-        EnseirbRoom::TD12,
-        EnseirbRoom::TD13,
-        EnseirbRoom::TD14,
-        EnseirbRoom::TD15,
-        EnseirbRoom::TD17,
-        EnseirbRoom::TD20,
-        EnseirbRoom::TD21,
-        EnseirbRoom::TD22,
-        EnseirbRoom::TD23,
-        EnseirbRoom::TD24,
-        EnseirbRoom::TD25,
-        EnseirbRoom::TD26,
-        EnseirbRoom::TD27,
-        EnseirbRoom::TD28,
-    ]);
-    let mut cal = init_ade_cal();
+pub async fn compute_zik_calendar(
+    store: Arc<CalendarStore>,
+    policy: Arc<ZikPolicy>,
+    window: TimeWindow,
+) -> Calendar {
+    compute_zik(store, &policy, window).await.0
+}
 
-    let cut_times: Vec<(DateTime<Utc>, DateTime<Utc>)> = get_cut_times(room_list.clone())
-        .await
-        .into_iter()
-        .tuple_windows()
-        .collect();
+/// The levels of the Zik over the window, as shown in the timetables: cached along with the Zik
+/// calendar of the window, and computed (and cached) only if neither is recent enough
+pub async fn get_zik_slots(
+    store: Arc<CalendarStore>,
+    policy: &ZikPolicy,
+    window: TimeWindow,
+) -> (Vec<ZikSlot>, DataNotice) {
+    let cached = store
+        .cache
+        .get_computed(&policy.site().name(), &zik_slots_key(window))
+        .and_then(|x| serde_json::from_str(&x).ok());
+    if let Some(slots) = cached {
+        return (slots, DataNotice::default());
+    }
 
-    for (start_time, end_time) in cut_times.iter() {
-        let allowed_activities = get_allowed_level(start_time, end_time, room_list.clone());
-        let start = DatePerhapsTime::DateTime(CalendarDateTime::Utc(start_time.clone()));
-        let end = DatePerhapsTime::DateTime(CalendarDateTime::Utc(end_time.clone()));
+    tracing::info!("Cache miss, downloading and parsing the Zik calendar...");
+    let (_, slots, notice) = compute_zik(store, policy, window).await;
 
-        let activity = match allowed_activities {
-            AllowedActivities::QuietPlaying(WindowPosition::Closed) => {
-                "Faible volume, fenêtre fermée."
-            }
-            AllowedActivities::QuietPlaying(WindowPosition::Open) => {
-                "Faible volume, fenêtre ouverte."
-            }
-            AllowedActivities::LoudPlaying(WindowPosition::Closed) => {
-                "Volume élevé, fenêtre fermée."
-            }
-            AllowedActivities::LoudPlaying(WindowPosition::Open) => {
-                "Volume élevé, fenêtre ouverte."
-            }
-            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Closed) => {
-                "Volume élevé et batterie, fenêtre fermée."
-            }
-            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open) => {
-                "Volume élevé et batterie, fenêtre ouverte."
-            }
-        };
+    (slots, notice)
+}
+
+/// Renders the levels of the Zik as an iCalendar calendar, with an event per slot
+pub fn zik_levels_to_calendar(
+    levels: &[(Interval, ZikLevel)],
+    notice: &DataNotice,
+    window: TimeWindow,
+) -> Calendar {
+    let mut cal = init_ade_cal();
+
+    if let Some(event) = notice_event(notice, window) {
+        cal.push(event);
+    }
+
+    for (slot, level) in levels {
+        let start = DatePerhapsTime::DateTime(CalendarDateTime::Utc(slot.start));
+        let end = DatePerhapsTime::DateTime(CalendarDateTime::Utc(slot.end));
 
         cal.push(
             Event::new()
-                .description(activity)
+                .description(&zik_description(level))
                 .location("Le Zik, Le Zik, Le Zik")
                 .starts(start)
                 .ends(end)
@@ -503,3 +666,412 @@ pub async fn get_zik_calendar() -> Calendar {
 
     cal.done()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        caching::cal_caching::{CacheConfig, CalendarCache},
+        calendar_parsing::rooms::RoomRegistry,
+        networking::{ade_api_handling::DataStatus, calendar_source::FixtureSource},
+    };
+    use proptest::prelude::*;
+
+    fn test_rooms(count: usize) -> Vec<Room> {
+        let mut catalogue = String::from(
+            "[[sites]]\nname = \"test\"\nade_url = \"http://localhost\"\nproject_id = 1\ndisplay_config_id = 1\n",
+        );
+        for i in 1..=count {
+            catalogue += &format!(
+                "[[sites.rooms]]\nshort_name = \"TD{i:02}\"\nname = \"Room {i}\"\nid = {i}\n"
+            );
+        }
+
+        RoomRegistry::from_toml(&catalogue)
+            .unwrap()
+            .default_site()
+            .rooms()
+            .to_vec()
+    }
+
+    fn catalogue() -> RoomRegistry {
+        RoomRegistry::from_toml(include_str!("../../rooms.toml")).unwrap()
+    }
+
+    fn zik_policy() -> ZikPolicy {
+        ZikPolicy::from_toml(include_str!("../../zik.toml"), &catalogue()).unwrap()
+    }
+
+    fn catalogue_rooms(short_names: &[&str]) -> Vec<Room> {
+        let site = catalogue().default_site();
+
+        short_names
+            .iter()
+            .map(|x| site.from_string(x.to_string()).unwrap())
+            .collect()
+    }
+
+    fn event(start: i64, length: i64) -> RoomEvent {
+        let start = DateTime::from_timestamp(start * 60, 0).unwrap();
+        RoomEvent {
+            start,
+            end: start + Duration::minutes(length),
+            summary: "Cours".to_string(),
+            description: None,
+            location: None,
+        }
+    }
+
+    // (start, length) in minutes for the events of each room
+    fn arb_schedules() -> impl Strategy<Value = Vec<Vec<(i64, i64)>>> {
+        prop::collection::vec(prop::collection::vec((0i64..2000, 1i64..240), 0..8), 1..6)
+    }
+
+    fn build_schedules(rooms: Vec<Room>, events: &[Vec<(i64, i64)>]) -> Vec<RoomSchedule> {
+        rooms
+            .into_iter()
+            .zip(events)
+            .map(|(room, events)| {
+                let mut schedule = RoomSchedule::empty(room);
+                schedule.events = events
+                    .iter()
+                    .map(|(start, length)| event(*start, *length))
+                    .collect();
+                schedule.events.sort_by_key(|x| x.start);
+                schedule
+            })
+            .collect()
+    }
+
+    fn fixture_store(source: FixtureSource) -> Arc<CalendarStore> {
+        Arc::new(CalendarStore {
+            source: Box::new(source),
+            cache: CalendarCache::in_memory(CacheConfig::default()),
+        })
+    }
+
+    // the day of the fixture calendars: TD06 is busy from 08:00 to 10:00 (UTC), TD15 from 09:00 to 12:00
+    fn fixture_window() -> TimeWindow {
+        TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 1).unwrap()
+    }
+
+    fn utc(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 10, 21, hour, 0, 0).unwrap()
+    }
+
+    fn fixture_source(rooms: &[Room]) -> FixtureSource {
+        FixtureSource::new()
+            .with(&rooms[0], include_str!("../../tests/fixtures/TD06.ics"))
+            .with(&rooms[1], include_str!("../../tests/fixtures/TD15.ics"))
+    }
+
+    #[tokio::test]
+    async fn free_rooms_are_computed_from_the_source() {
+        // TD07 isn't in the source
+        let rooms = catalogue_rooms(&["TD06", "TD15", "TD07"]);
+        let store = fixture_store(fixture_source(&rooms));
+
+        let (slots, notice) = get_free_rooms_slots(
+            store,
+            catalogue().default_site(),
+            Arc::new(rooms),
+            fixture_window(),
+        )
+        .await;
+
+        let summary: Vec<(DateTime<Utc>, DateTime<Utc>, String, String)> = slots
+            .iter()
+            .map(|x| {
+                (
+                    x.start,
+                    x.end,
+                    x.free_rooms.iter().map(|x| &x.short_name).join(","),
+                    x.busy_rooms.iter().map(|x| &x.room.short_name).join(","),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (utc(8), utc(9), "TD15".to_string(), "TD06".to_string()),
+                (utc(9), utc(10), "".to_string(), "TD06,TD15".to_string()),
+                (utc(10), utc(12), "TD06".to_string(), "TD15".to_string()),
+            ]
+        );
+        assert_eq!(notice.missing_rooms[0].short_name, "TD07");
+        assert!(notice.stale_rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_last_calendar_is_used_the_day_after_an_outage() {
+        let site = catalogue().default_site();
+        let rooms = Arc::new(catalogue_rooms(&["TD06"]));
+        // busy on the 21st and on the 22nd
+        let calendar = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n\
+            BEGIN:VEVENT\r\nDTSTART:20241021T080000Z\r\nDTEND:20241021T100000Z\r\nSUMMARY:Analyse\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nDTSTART:20241022T130000Z\r\nDTEND:20241022T150000Z\r\nSUMMARY:Réseaux\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let store = fixture_store(FixtureSource::new().with(&rooms[0], calendar));
+
+        // fetched on the 21st, for the 21st and the 22nd
+        let first_day = NaiveDate::from_ymd_opt(2024, 10, 21).unwrap();
+        let schedules = get_room_schedules(
+            store.clone(),
+            site.clone(),
+            rooms.clone(),
+            TimeWindow::from_days(first_day, 2).unwrap(),
+        )
+        .await;
+        assert_eq!(schedules[0].status, DataStatus::Fresh);
+
+        // ADE is down on the 22nd, whose window was never fetched
+        let Ok(store) = Arc::try_unwrap(store) else {
+            panic!("the store is still shared");
+        };
+        let store = Arc::new(CalendarStore {
+            source: Box::new(FixtureSource::new()),
+            cache: store.cache,
+        });
+        let next_day = TimeWindow::from_days(first_day.succ_opt().unwrap(), 1).unwrap();
+        let schedules = get_room_schedules(store, site, rooms, next_day).await;
+
+        assert!(matches!(schedules[0].status, DataStatus::Stale(_)));
+        // only the events of the 22nd are left
+        assert_eq!(schedules[0].events.len(), 1);
+        assert_eq!(schedules[0].events[0].summary, "Réseaux");
+    }
+
+    #[tokio::test]
+    async fn rooms_free_at_a_time_and_for_an_interval() {
+        let rooms = catalogue_rooms(&["TD06", "TD15"]);
+        let store = fixture_store(fixture_source(&rooms));
+        let site = catalogue().default_site();
+        let rooms = Arc::new(rooms);
+
+        let (free_rooms, notice) = get_rooms_free_at(
+            store.clone(),
+            site.clone(),
+            rooms.clone(),
+            fixture_window(),
+            utc(8),
+        )
+        .await;
+        assert!(notice.is_empty());
+        assert_eq!(free_rooms.len(), 1);
+        assert_eq!(free_rooms[0].room.short_name, "TD15");
+        assert_eq!(free_rooms[0].free_until, Some(utc(9)));
+        assert_eq!(free_rooms[0].free_minutes, Some(60));
+
+        let (found_rooms, _) = find_free_rooms(
+            store,
+            site,
+            rooms,
+            fixture_window(),
+            Interval {
+                start: utc(12),
+                end: utc(13),
+            },
+        )
+        .await;
+        let found: Vec<(String, Option<DateTime<Utc>>)> = found_rooms
+            .iter()
+            .map(|x| (x.room.short_name.clone(), x.free_from))
+            .collect();
+        // TD06 has been free for longer
+        assert_eq!(
+            found,
+            vec![
+                ("TD06".to_string(), Some(utc(10))),
+                ("TD15".to_string(), Some(utc(12)))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn zik_calendar_is_computed_from_the_source_and_cached() {
+        let policy = Arc::new(zik_policy());
+        let rooms = catalogue_rooms(&["TD06", "TD15"]);
+        // every other room of the policy is free all day
+        let source = policy
+            .rooms()
+            .iter()
+            .fold(fixture_source(&rooms), |source, room| {
+                match rooms.contains(room) {
+                    true => source,
+                    false => source.with(room, "BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n"),
+                }
+            });
+        let store = fixture_store(source);
+
+        let cal = compute_zik_calendar(store.clone(), policy.clone(), fixture_window())
+            .await
+            .to_string()
+            .replace("\r\n ", "");
+
+        // TD06 is right above the Zik (commas are escaped in the calendar)
+        assert!(cal.contains("DTSTART:20241021T080000Z"));
+        assert!(cal.contains("Faible volume\\, fenêtre fermée."));
+        assert!(!cal.contains("Données ADE incomplètes"));
+        assert!(store
+            .cache
+            .get_cal(&policy.site().name(), &zik_cal_key(fixture_window()))
+            .is_some());
+
+        // the timetables get the same levels from the cache
+        let (slots, notice) = get_zik_slots(store.clone(), &policy, fixture_window()).await;
+        assert!(notice.is_empty());
+        assert_eq!(slots[0].start, utc(8));
+        assert_eq!(
+            slots[0].allowed_activities,
+            AllowedActivities::QuietPlaying(WindowPosition::Closed)
+        );
+        assert!(store
+            .cache
+            .get_computed(&policy.site().name(), &zik_slots_key(fixture_window()))
+            .is_some());
+    }
+
+    #[test]
+    fn event_inside_a_slot_makes_the_room_busy() {
+        // the 10 minute gap between the first two events gets merged into a single slot,
+        // which strictly contains the second event of TD02
+        let mut schedules = build_schedules(test_rooms(2), &[vec![(0, 60), (70, 60)], vec![]]);
+        schedules[1].events = vec![event(62, 5)];
+
+        for slot in compute_free_rooms_slots(&schedules) {
+            let td02_busy = slot.busy_rooms.iter().any(|x| x.room.short_name == "TD02");
+            let overlaps =
+                slot.start < schedules[1].events[0].end && schedules[1].events[0].start < slot.end;
+            assert_eq!(td02_busy, overlaps);
+        }
+    }
+
+    #[test]
+    fn missing_rooms_are_never_free() {
+        let mut schedules =
+            build_schedules(test_rooms(3), &[vec![(0, 60)], vec![(120, 60)], vec![]]);
+        let fetched_at = DateTime::from_timestamp(0, 0).unwrap();
+        schedules[1].status = DataStatus::Stale(fetched_at);
+        schedules[2] = RoomSchedule::missing(schedules[2].room.clone());
+
+        let slots = compute_free_rooms_slots(&schedules);
+        assert!(!slots.is_empty());
+
+        for slot in &slots {
+            assert!(slot.free_rooms.iter().all(|x| x.short_name != "TD03"));
+            assert!(slot.busy_rooms.iter().all(|x| x.room.short_name != "TD03"));
+            // the stale room is still used
+            assert_eq!(slot.free_rooms.len() + slot.busy_rooms.len(), 2);
+        }
+        let notice = DataNotice::from_schedules(&schedules);
+        assert_eq!(notice.stale_rooms[0].room.short_name, "TD02");
+        assert_eq!(notice.stale_rooms[0].fetched_at, fetched_at);
+        assert_eq!(notice.missing_rooms[0].short_name, "TD03");
+
+        let window =
+            TimeWindow::from_days(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(), 1).unwrap();
+        // long lines are folded
+        let cal = free_rooms_slots_to_calendar(&slots, &notice, window)
+            .to_string()
+            .replace("\r\n ", "");
+        assert!(cal.contains("SUMMARY:Données ADE incomplètes"));
+        assert!(cal.contains("Données indisponibles : TD03"));
+    }
+
+    #[test]
+    fn missing_room_near_the_zik_is_considered_busy() {
+        let mut schedules = build_schedules(catalogue_rooms(&["TD06", "TD15"]), &[vec![], vec![]]);
+        schedules[0] = RoomSchedule::missing(schedules[0].room.clone());
+
+        let window = Interval {
+            start: DateTime::from_timestamp(0, 0).unwrap(),
+            end: DateTime::from_timestamp(86400, 0).unwrap(),
+        };
+        fill_missing_schedules(&mut schedules, &window);
+
+        let levels = compute_zik_levels(&zik_policy(), &schedules);
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].0, window);
+        assert_eq!(
+            levels[0].1.allowed_activities,
+            AllowedActivities::QuietPlaying(WindowPosition::Closed)
+        );
+    }
+
+    #[test]
+    fn busy_td06_lowers_the_zik_level() {
+        let rooms = catalogue_rooms(&["TD06", "TD15"]);
+        let timezone = chrono_tz::Europe::Paris;
+        let schedules = vec![
+            RoomSchedule::parse(
+                rooms[0].clone(),
+                include_str!("../../tests/fixtures/TD06.ics"),
+                timezone,
+            ),
+            RoomSchedule::parse(
+                rooms[1].clone(),
+                include_str!("../../tests/fixtures/TD15.ics"),
+                timezone,
+            ),
+        ];
+        assert_eq!(schedules[0].events.len(), 1);
+
+        let td06_course = schedules[0].events[0].interval();
+        let levels = compute_zik_levels(&zik_policy(), &schedules);
+        assert!(levels.iter().any(|(slot, _)| slot.overlaps(&td06_course)));
+
+        for (slot, level) in levels {
+            if slot.overlaps(&td06_course) {
+                assert_eq!(
+                    level.allowed_activities,
+                    AllowedActivities::QuietPlaying(WindowPosition::Closed)
+                );
+            } else {
+                assert_ne!(
+                    level.allowed_activities,
+                    AllowedActivities::QuietPlaying(WindowPosition::Closed)
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn free_rooms_never_have_an_overlapping_event(events in arb_schedules()) {
+            let schedules = build_schedules(test_rooms(events.len()), &events);
+
+            for slot in compute_free_rooms_slots(&schedules) {
+                let interval = Interval { start: slot.start, end: slot.end };
+
+                for schedule in &schedules {
+                    let busy = schedule.events.iter().any(|x| x.interval().overlaps(&interval));
+                    let listed_free = slot
+                        .free_rooms
+                        .iter()
+                        .any(|x| x.id == schedule.room.id());
+
+                    prop_assert_eq!(listed_free, !busy);
+                }
+            }
+        }
+
+        #[test]
+        fn zik_level_accounts_for_every_overlapping_event(events in arb_schedules()) {
+            // the rooms next to the Zik
+            let schedules = build_schedules(catalogue_rooms(&["TD06", "TD07", "TD17"]), &events);
+
+            for (slot, level) in compute_zik_levels(&zik_policy(), &schedules) {
+                let busy = schedules
+                    .iter()
+                    .flat_map(|x| x.events.iter())
+                    .any(|x| x.interval().overlaps(&slot));
+
+                if busy {
+                    prop_assert!(level.allowed_activities == AllowedActivities::QuietPlaying(WindowPosition::Closed));
+                } else {
+                    prop_assert!(level.allowed_activities == AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open));
+                }
+            }
+        }
+    }
+}
@@ -1,180 +1,693 @@
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum EnseirbRoom {
-    TD01,
-    TD02,
-    TD03,
-    TD04,
-    TD05,
-    TD06,
-    TD07,
-    TD08,
-    TD09,
-    TD10,
-    TD11,
-    TD12,
-    TD13,
-    TD14,
-    TD15,
-    TD16,
-    TD17,
-    TD18,
-    TD19,
-    TD20,
-    TD21,
-    TD22,
-    TD23,
-    TD24,
-    TD25,
-    TD26,
-    TD27,
-    TD28,
-}
-
-unsafe impl Sync for EnseirbRoom {}
-
-unsafe impl Send for EnseirbRoom {}
-
-impl EnseirbRoom {
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Room {
+    short_name: String,
+    name: Option<String>,
+    id: u32,
+    #[serde(default)]
+    aliases: Vec<String>,
+    floor: Option<i32>,
+    wing: Option<String>,
+    capacity: Option<u32>,
+    // free-form, e.g. "projector", "whiteboard", "power-outlets"
+    #[serde(default)]
+    equipment: Vec<String>,
+    #[serde(default)]
+    wheelchair_accessible: bool,
+}
+
+impl Room {
     pub fn name(&self) -> Option<String> {
-        match self {
-            EnseirbRoom::TD01 => None,
-            EnseirbRoom::TD02 => None,
-            EnseirbRoom::TD03 => None,
-            EnseirbRoom::TD04 => Some("EA-S101/S102 (TD04)".to_string()),
-            EnseirbRoom::TD05 => Some("EA-S104/S105 (TD05)".to_string()),
-            EnseirbRoom::TD06 => Some("EA-S106/S107 (TD06)".to_string()),
-            EnseirbRoom::TD07 => Some("EA-S108/S109 (TD07)".to_string()),
-            EnseirbRoom::TD08 => Some("EA-S110/S111 (TD08)".to_string()),
-            EnseirbRoom::TD09 => Some("EA-S112/S113 (TD09)".to_string()),
-            EnseirbRoom::TD10 => Some("EA-S114 (TD10)".to_string()),
-            EnseirbRoom::TD11 => Some("EA-S115/S116 (TD11)".to_string()),
-            EnseirbRoom::TD12 => Some("EA-S117/S118 (TD12)".to_string()),
-            EnseirbRoom::TD13 => Some("EA-S119/S120 (TD13)".to_string()),
-            EnseirbRoom::TD14 => Some("EA-S121/S122 (TD14)".to_string()),
-            EnseirbRoom::TD15 => Some("EA-S225 (TD15)".to_string()),
-            EnseirbRoom::TD16 => None,
-            EnseirbRoom::TD17 => Some("EA-S008/S009 (TD17)".to_string()),
-            EnseirbRoom::TD18 => None,
-            EnseirbRoom::TD19 => None,
-            EnseirbRoom::TD20 => Some("EB-P010/P011 (TD20)".to_string()),
-            EnseirbRoom::TD21 => Some("EB-P117 (TD21)".to_string()),
-            EnseirbRoom::TD22 => Some("EB-P118/P119 (TD22)".to_string()),
-            EnseirbRoom::TD23 => Some("EB-P121 (TD23)".to_string()),
-            EnseirbRoom::TD24 => Some("EB-P123 (TD24)".to_string()),
-            EnseirbRoom::TD25 => Some("EB-P145 (TD25)".to_string()),
-            EnseirbRoom::TD26 => Some("EB-P147 (TD26)".to_string()),
-            EnseirbRoom::TD27 => Some("EB-P148/P150 (TD27)".to_string()),
-            EnseirbRoom::TD28 => Some("EB-P153/P156 (TD28)".to_string()),
-        }
+        self.name.clone()
     }
 
     pub fn short_name(&self) -> String {
-        match self {
-            EnseirbRoom::TD01 => "TD01".to_string(),
-            EnseirbRoom::TD02 => "TD02".to_string(),
-            EnseirbRoom::TD03 => "TD03".to_string(),
-            EnseirbRoom::TD04 => "TD04".to_string(),
-            EnseirbRoom::TD05 => "TD05".to_string(),
-            EnseirbRoom::TD06 => "TD06".to_string(),
-            EnseirbRoom::TD07 => "TD07".to_string(),
-            EnseirbRoom::TD08 => "TD08".to_string(),
-            EnseirbRoom::TD09 => "TD09".to_string(),
-            EnseirbRoom::TD10 => "TD10".to_string(),
-            EnseirbRoom::TD11 => "TD11".to_string(),
-            EnseirbRoom::TD12 => "TD12".to_string(),
-            EnseirbRoom::TD13 => "TD13".to_string(),
-            EnseirbRoom::TD14 => "TD14".to_string(),
-            EnseirbRoom::TD15 => "TD15".to_string(),
-            EnseirbRoom::TD16 => "TD16".to_string(),
-            EnseirbRoom::TD17 => "TD17".to_string(),
-            EnseirbRoom::TD18 => "TD18".to_string(),
-            EnseirbRoom::TD19 => "TD19".to_string(),
-            EnseirbRoom::TD20 => "TD20".to_string(),
-            EnseirbRoom::TD21 => "TD21".to_string(),
-            EnseirbRoom::TD22 => "TD22".to_string(),
-            EnseirbRoom::TD23 => "TD23".to_string(),
-            EnseirbRoom::TD24 => "TD24".to_string(),
-            EnseirbRoom::TD25 => "TD25".to_string(),
-            EnseirbRoom::TD26 => "TD26".to_string(),
-            EnseirbRoom::TD27 => "TD27".to_string(),
-            EnseirbRoom::TD28 => "TD28".to_string(),
+        self.short_name.clone()
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn floor(&self) -> Option<i32> {
+        self.floor
+    }
+
+    pub fn wing(&self) -> Option<String> {
+        self.wing.clone()
+    }
+
+    pub fn capacity(&self) -> Option<u32> {
+        self.capacity
+    }
+
+    pub fn equipment(&self) -> Vec<String> {
+        self.equipment.clone()
+    }
+
+    pub fn wheelchair_accessible(&self) -> bool {
+        self.wheelchair_accessible
+    }
+
+    // "wheelchair-access" stands for the accessibility of the room, anything else for its equipment
+    fn has(&self, feature: &str) -> bool {
+        if feature.eq_ignore_ascii_case("wheelchair-access") {
+            return self.wheelchair_accessible;
+        }
+        self.equipment
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(feature))
+    }
+
+    // a room matches its short name or any of its aliases, case-insensitively
+    fn matches(&self, val: &str) -> bool {
+        let val = val.to_lowercase();
+        self.short_name.to_lowercase() == val
+            || self.aliases.iter().any(|x| x.to_lowercase() == val)
+    }
+}
+
+/// The requirements the rooms of a request have to meet, checked against the catalogue
+/// before anything is fetched. Rooms whose capacity is unknown never meet a minimum capacity.
+#[derive(Clone, Debug, Default)]
+pub struct RoomFilter {
+    pub min_capacity: Option<u32>,
+    pub features: Vec<String>,
+}
+
+impl RoomFilter {
+    pub fn matches(&self, room: &Room) -> bool {
+        let big_enough = match self.min_capacity {
+            Some(min_capacity) => room.capacity.is_some_and(|x| x >= min_capacity),
+            None => true,
+        };
+
+        big_enough && self.features.iter().all(|x| room.has(x))
+    }
+
+    pub fn apply(&self, rooms: &[Room]) -> Arc<Vec<Room>> {
+        Arc::new(rooms.iter().filter(|x| self.matches(x)).cloned().collect())
+    }
+
+    // a feature no room of the site has is most likely a typo, rather than a filter that drops every room
+    pub fn check(&self, site: &Site) -> Result<(), String> {
+        let features = site.features();
+
+        match self
+            .features
+            .iter()
+            .find(|x| !features.contains(&x.to_lowercase()))
+        {
+            Some(unknown) => Err(format!(
+                "unknown feature {unknown} (the rooms of site {} have: {})",
+                site.name(),
+                features.join(", ")
+            )),
+            None => Ok(()),
         }
     }
+}
+
+/// Sorts the rooms by short name and drops the duplicates, so that a selection is the same
+/// whatever the order it was asked in
+///
+/// ```
+/// use ade::{canonical_selection, RoomRegistry};
+///
+/// let registry = RoomRegistry::from_toml(
+///     r#"
+///     [[sites]]
+///     name = "enseirb"
+///     ade_url = "https://ade.example.com"
+///     project_id = 1
+///     display_config_id = 71
+///     rooms = [
+///         { short_name = "TD06", id = 3258 },
+///         { short_name = "TD07", id = 3257 },
+///     ]
+///     "#,
+/// )?;
+/// let site = registry.default_site();
+/// let room = |name: &str| site.from_string(name.to_string()).unwrap();
+///
+/// assert_eq!(
+///     canonical_selection([room("TD07"), room("TD06"), room("TD07")]),
+///     canonical_selection([room("TD06"), room("TD07")])
+/// );
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn canonical_selection(rooms: impl IntoIterator<Item = Room>) -> Arc<Vec<Room>> {
+    let mut rooms: Vec<Room> = rooms.into_iter().collect();
+    rooms.sort_by(|a, b| (&a.short_name, a.id).cmp(&(&b.short_name, b.id)));
+    rooms.dedup_by_key(|x| x.id);
 
-    pub fn id(&self) -> Option<u16> {
+    Arc::new(rooms)
+}
+
+/// A place of the building model that isn't an ADE room (like the Zik)
+#[derive(Debug, Deserialize)]
+pub struct Place {
+    name: String,
+    floor: Option<i32>,
+    wing: Option<String>,
+}
+
+/// How the `from` end of a link sits relative to its `to` end
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Relation {
+    Above,
+    Below,
+    NextDoor,
+    SameCorridor,
+}
+
+impl Relation {
+    fn default_distance(&self) -> u32 {
         match self {
-            EnseirbRoom::TD01 => Some(3224),
-            EnseirbRoom::TD02 => Some(3223),
-            EnseirbRoom::TD03 => Some(3222),
-            EnseirbRoom::TD04 => Some(3260),
-            EnseirbRoom::TD05 => Some(3259),
-            EnseirbRoom::TD06 => Some(3258),
-            EnseirbRoom::TD07 => Some(3254),
-            EnseirbRoom::TD08 => Some(3253),
-            EnseirbRoom::TD09 => Some(3252),
-            EnseirbRoom::TD10 => Some(3251),
-            EnseirbRoom::TD11 => Some(3250),
-            EnseirbRoom::TD12 => Some(3249),
-            EnseirbRoom::TD13 => Some(3248),
-            EnseirbRoom::TD14 => Some(3247),
-            EnseirbRoom::TD15 => Some(3280),
-            EnseirbRoom::TD16 => None,
-            EnseirbRoom::TD17 => Some(3230),
-            EnseirbRoom::TD18 => None,
-            EnseirbRoom::TD19 => None,
-            EnseirbRoom::TD20 => Some(3296),
-            EnseirbRoom::TD21 => Some(3329),
-            EnseirbRoom::TD22 => Some(3330),
-            EnseirbRoom::TD23 => Some(3331),
-            EnseirbRoom::TD24 => Some(3327),
-            EnseirbRoom::TD25 => Some(3314),
-            EnseirbRoom::TD26 => Some(3315),
-            EnseirbRoom::TD27 => Some(3316),
-            EnseirbRoom::TD28 => Some(3318),
+            Relation::Above | Relation::Below | Relation::NextDoor => 1,
+            Relation::SameCorridor => 2,
         }
     }
+}
+
+struct Node {
+    name: String,
+    floor: Option<i32>,
+    wing: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Link {
+    from: String,
+    to: String,
+    relation: Relation,
+    distance: Option<u32>,
+}
+
+/// A school/building served by an ADE instance, along with the rooms it contains
+#[derive(Debug, Deserialize)]
+pub struct Site {
+    name: String,
+    ade_url: String,
+    project_id: u32,
+    display_config_id: u32,
+    // the days, the times given in the requests and the events ADE publishes without a UTC offset
+    // (floating times and all-day events) are in this timezone, whatever the host's
+    #[serde(default = "default_timezone")]
+    timezone: Tz,
+    rooms: Vec<Room>,
+    #[serde(default)]
+    places: Vec<Place>,
+    #[serde(default)]
+    links: Vec<Link>,
+}
+
+fn default_timezone() -> Tz {
+    chrono_tz::Europe::Paris
+}
+
+impl Site {
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    // the current date at the site
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.timezone).date_naive()
+    }
+
+    pub fn from_string(&self, val: String) -> Option<Room> {
+        self.rooms.iter().find(|x| x.matches(&val)).cloned()
+    }
+
+    pub fn rooms(&self) -> Arc<Vec<Room>> {
+        Arc::new(self.rooms.clone())
+    }
+
+    // format: short names or aliases separated by a , (spaces and empty names are ignored).
+    // returns the selection of the known rooms along with the names that match no room, which
+    // the URL parameters leave out and the command line rejects
+    pub fn select_rooms(&self, list: &str) -> (Arc<Vec<Room>>, Vec<String>) {
+        let (rooms, unknown): (Vec<_>, Vec<_>) = list
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| self.from_string(x.to_string()).ok_or_else(|| x.to_string()))
+            .partition(|x| x.is_ok());
+
+        (
+            canonical_selection(rooms.into_iter().flatten()),
+            unknown.into_iter().filter_map(|x| x.err()).collect(),
+        )
+    }
+
+    // the features the rooms can be filtered on: the equipment of the rooms (lowercase, sorted)
+    // and "wheelchair-access"
+    pub fn features(&self) -> Vec<String> {
+        self.rooms
+            .iter()
+            .flat_map(|x| x.equipment.iter().map(|x| x.to_lowercase()))
+            .chain(["wheelchair-access".to_string()])
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
 
-    pub fn from_string(val: String) -> Option<Self> {
-        match val.to_lowercase().as_str() {
-            "1" | "td1" => Some(Self::TD01),
-            "2" | "td2" => Some(Self::TD02),
-            "3" | "td3" => Some(Self::TD03),
-            "4" | "td4" => Some(Self::TD04),
-            "5" | "td5" => Some(Self::TD05),
-            "6" | "td6" => Some(Self::TD06),
-            "7" | "td7" => Some(Self::TD07),
-            "8" | "td8" => Some(Self::TD08),
-            "9" | "td9" => Some(Self::TD09),
-            "10" | "td10" => Some(Self::TD10),
-            "11" | "td11" => Some(Self::TD11),
-            "12" | "td12" => Some(Self::TD12),
-            "13" | "td13" => Some(Self::TD13),
-            "14" | "td14" => Some(Self::TD14),
-            "15" | "td15" => Some(Self::TD15),
-            "16" | "td16" => Some(Self::TD16),
-            "17" | "td17" => Some(Self::TD17),
-            "18" | "td18" => Some(Self::TD18),
-            "19" | "td19" => Some(Self::TD19),
-            "20" | "td20" => Some(Self::TD20),
-            "21" | "td21" => Some(Self::TD21),
-            "22" | "td22" => Some(Self::TD22),
-            "23" | "td23" => Some(Self::TD23),
-            "24" | "td24" => Some(Self::TD24),
-            "25" | "td25" => Some(Self::TD25),
-            "26" | "td26" => Some(Self::TD26),
-            "27" | "td27" => Some(Self::TD27),
-            "28" | "td28" => Some(Self::TD28),
-            &_ => None,
+    // a node of the building model, found by the short name of a room (or one of its aliases),
+    // or the name of a place
+    fn node(&self, val: &str) -> Option<Node> {
+        if let Some(room) = self.rooms.iter().find(|x| x.matches(val)) {
+            return Some(Node {
+                name: room.short_name.clone(),
+                floor: room.floor,
+                wing: room.wing.clone(),
+            });
         }
+
+        self.places
+            .iter()
+            .find(|x| x.name.to_lowercase() == val.to_lowercase())
+            .map(|x| Node {
+                name: x.name.clone(),
+                floor: x.floor,
+                wing: x.wing.clone(),
+            })
     }
 
-    pub fn url(&self, start_date: String, end_date: String) -> Option<String> {
-        let resid = self.id();
-        match resid {
-            Some(id) => {
-                return Some(format!("https://adeapp.bordeaux-inp.fr/jsp/custom/modules/plannings/anonymous_cal.jsp?resources={id}&projectId=1&calType=ical&firstDate={start_date}&lastDate={end_date}&displayConfigId=71"));
+    /// The distance from a room or place to every room and place it's connected to (itself included),
+    /// by room short name or place name. The distance is the length of the shortest path along the
+    /// links of the building model. Returns None if the room or place doesn't exist.
+    pub fn distances_from(&self, from: &str) -> Option<HashMap<String, u32>> {
+        let start = self.node(from)?.name;
+
+        let mut neighbours: HashMap<String, Vec<(String, u32)>> = HashMap::new();
+        for link in &self.links {
+            // the links are checked when the catalogue is loaded
+            let (Some(Node { name: a, .. }), Some(Node { name: b, .. })) =
+                (self.node(&link.from), self.node(&link.to))
+            else {
+                continue;
+            };
+            let distance = link
+                .distance
+                .unwrap_or_else(|| link.relation.default_distance());
+            neighbours
+                .entry(a.clone())
+                .or_default()
+                .push((b.clone(), distance));
+            neighbours.entry(b).or_default().push((a, distance));
+        }
+
+        let mut distances: HashMap<String, u32> = HashMap::new();
+        let mut queue = BinaryHeap::from([Reverse((0, start))]);
+        while let Some(Reverse((distance, node))) = queue.pop() {
+            if distances.contains_key(&node) {
+                continue;
             }
-            None => None,
+            for (next, length) in neighbours.get(&node).into_iter().flatten() {
+                if !distances.contains_key(next) {
+                    queue.push(Reverse((distance + length, next.clone())));
+                }
+            }
+            distances.insert(node, distance);
         }
+
+        Some(distances)
+    }
+
+    pub fn url(&self, room: &Room, start_date: String, end_date: String) -> String {
+        let ade_url = self.ade_url.trim_end_matches('/');
+        let id = room.id;
+        let project_id = self.project_id;
+        let display_config_id = self.display_config_id;
+        format!("{ade_url}/jsp/custom/modules/plannings/anonymous_cal.jsp?resources={id}&projectId={project_id}&calType=ical&firstDate={start_date}&lastDate={end_date}&displayConfigId={display_config_id}")
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        // the site name is used as a cache directory name
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "site name \"{}\" must only contain letters, digits, '-' and '_'",
+                self.name
+            ))?;
+        }
+
+        let mut ids = HashSet::new();
+        let mut names = HashSet::new();
+        for room in &self.rooms {
+            if room.id == 0 {
+                return Err(format!(
+                    "site {}: room {} has an invalid ADE resource id (0)",
+                    self.name, room.short_name
+                ))?;
+            }
+            if !ids.insert(room.id) {
+                return Err(format!(
+                    "site {}: room {} reuses the ADE resource id {} of another room",
+                    self.name, room.short_name, room.id
+                ))?;
+            }
+            let room_names: HashSet<String> = room
+                .aliases
+                .iter()
+                .chain([&room.short_name])
+                .map(|x| x.to_lowercase())
+                .collect();
+            for alias in room_names {
+                if !names.insert(alias.clone()) {
+                    return Err(format!(
+                        "site {}: room {}: the name {alias} is already used by another room",
+                        self.name, room.short_name
+                    ))?;
+                }
+            }
+        }
+
+        for place in &self.places {
+            if !names.insert(place.name.to_lowercase()) {
+                return Err(format!(
+                    "site {}: the place name {} is already used by a room or another place",
+                    self.name, place.name
+                ))?;
+            }
+        }
+
+        for link in &self.links {
+            let (Some(from), Some(to)) = (self.node(&link.from), self.node(&link.to)) else {
+                return Err(format!(
+                    "site {}: the link from {} to {} uses an unknown room or place",
+                    self.name, link.from, link.to
+                ))?;
+            };
+
+            // rooms above each other have to be on different floors, and rooms along the same
+            // corridor on the same floor and in the same wing, when they're known
+            let consistent = match (link.relation, from.floor, to.floor) {
+                (Relation::Above, Some(from_floor), Some(to_floor)) => from_floor > to_floor,
+                (Relation::Below, Some(from_floor), Some(to_floor)) => from_floor < to_floor,
+                (Relation::NextDoor | Relation::SameCorridor, _, _) => {
+                    from.floor.zip(to.floor).is_none_or(|(a, b)| a == b)
+                        && from
+                            .wing
+                            .as_ref()
+                            .zip(to.wing.as_ref())
+                            .is_none_or(|(a, b)| a == b)
+                }
+                _ => true,
+            };
+            if !consistent {
+                return Err(format!(
+                    "site {}: {} can't be {:?} {} given their floors",
+                    self.name, link.from, link.relation, link.to
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct RoomCatalogue {
+    sites: Vec<Site>,
+}
+
+/// The sites and rooms known to the app, loaded at startup from the room catalogue file.
+/// The first site of the file is the default one.
+///
+/// ```
+/// use ade::{RoomFilter, RoomRegistry};
+///
+/// let registry = RoomRegistry::from_toml(
+///     r#"
+///     [[sites]]
+///     name = "enseirb"
+///     ade_url = "https://ade.example.com"
+///     project_id = 1
+///     display_config_id = 71
+///
+///     [[sites.rooms]]
+///     short_name = "TD06"
+///     name = "EA-S106/S107 (TD06)"
+///     id = 3258
+///     aliases = ["6"]
+///     capacity = 30
+///     equipment = ["projector"]
+///
+///     [[sites.rooms]]
+///     short_name = "TD07"
+///     id = 3257
+///     capacity = 20
+///     "#,
+/// )?;
+///
+/// let site = registry.site("ENSEIRB").unwrap();
+/// assert_eq!(site.from_string("6".to_string()).unwrap().short_name(), "TD06");
+///
+/// let filter = RoomFilter {
+///     min_capacity: Some(25),
+///     features: vec!["projector".to_string()],
+/// };
+/// assert_eq!(filter.apply(&site.rooms()).len(), 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct RoomRegistry {
+    sites: Vec<Arc<Site>>,
+}
+
+impl RoomRegistry {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read room catalogue {}: {e}", path.display()))?;
+
+        Self::from_toml(&data)
+            .map_err(|e| format!("invalid room catalogue {}: {e}", path.display()).into())
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self, Box<dyn Error>> {
+        let catalogue: RoomCatalogue = toml::from_str(data)?;
+
+        if catalogue.sites.is_empty() {
+            return Err("no site defined")?;
+        }
+
+        let mut site_names = HashSet::new();
+        for site in &catalogue.sites {
+            site.validate()?;
+            if !site_names.insert(site.name.to_lowercase()) {
+                return Err(format!("site {} is defined twice", site.name))?;
+            }
+        }
+
+        Ok(Self {
+            sites: catalogue.sites.into_iter().map(Arc::new).collect(),
+        })
+    }
+
+    pub fn sites(&self) -> &[Arc<Site>] {
+        &self.sites
+    }
+
+    pub fn default_site(&self) -> Arc<Site> {
+        self.sites[0].clone()
+    }
+
+    pub fn site(&self, val: &str) -> Option<Arc<Site>> {
+        self.sites
+            .iter()
+            .find(|x| x.name.to_lowercase() == val.to_lowercase())
+            .cloned()
+    }
+
+    // the site with the given name, or the default one if there is no name
+    pub fn site_or_default(&self, val: Option<&str>) -> Result<Arc<Site>, String> {
+        match val {
+            Some(thing) => self
+                .site(thing)
+                .ok_or_else(|| format!("Unknown site: {thing}")),
+            None => Ok(self.default_site()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE: &str = r#"
+        [[sites]]
+        name = "test"
+        ade_url = "http://localhost"
+        project_id = 1
+        display_config_id = 1
+
+        [[sites.rooms]]
+        short_name = "A1"
+        id = 1
+        floor = 1
+        aliases = ["a-one"]
+        capacity = 30
+        equipment = ["projector", "whiteboard"]
+        wheelchair_accessible = true
+
+        [[sites.rooms]]
+        short_name = "A2"
+        id = 2
+        floor = 1
+        capacity = 60
+        equipment = ["Projector"]
+
+        [[sites.rooms]]
+        short_name = "B0"
+        id = 3
+        floor = 0
+
+        [[sites.places]]
+        name = "Hall"
+        floor = 0
+
+        [[sites.links]]
+        from = "a-one"
+        to = "A2"
+        relation = "next-door"
+
+        [[sites.links]]
+        from = "A2"
+        to = "Hall"
+        relation = "above"
+        distance = 3
+
+        [[sites.links]]
+        from = "A1"
+        to = "Hall"
+        relation = "above"
+
+        [[sites.links]]
+        from = "B0"
+        to = "Hall"
+        relation = "same-corridor"
+    "#;
+
+    #[test]
+    fn sites_and_rooms_are_selected_by_name() {
+        let registry = RoomRegistry::from_toml(SITE).unwrap();
+        assert_eq!(registry.site_or_default(None).unwrap().name(), "test");
+        assert_eq!(
+            registry.site_or_default(Some("TEST")).unwrap().name(),
+            "test"
+        );
+        assert!(registry.site_or_default(Some("other")).is_err());
+
+        let (rooms, unknown) = registry.default_site().select_rooms("B0, a-one,,A1,Z9");
+        let names: Vec<String> = rooms.iter().map(|x| x.short_name()).collect();
+        assert_eq!(names, ["A1", "B0"]);
+        assert_eq!(unknown, ["Z9"]);
+    }
+
+    #[test]
+    fn distances_follow_the_shortest_path() {
+        let registry = RoomRegistry::from_toml(SITE).unwrap();
+        let distances = registry.default_site().distances_from("hall").unwrap();
+
+        assert_eq!(distances["Hall"], 0);
+        assert_eq!(distances["A1"], 1);
+        // through A1 rather than the direct link
+        assert_eq!(distances["A2"], 2);
+        assert_eq!(distances["B0"], 2);
+        assert!(registry.default_site().distances_from("C3").is_none());
+    }
+
+    #[test]
+    fn links_have_to_match_the_floors() {
+        let catalogue = SITE.replace(
+            "from = \"A2\"\n        to = \"Hall\"\n        relation = \"above\"",
+            "from = \"A2\"\n        to = \"Hall\"\n        relation = \"below\"",
+        );
+        assert_ne!(catalogue, SITE);
+        assert!(RoomRegistry::from_toml(&catalogue).is_err());
+    }
+
+    #[test]
+    fn filters_check_capacity_and_features() {
+        let registry = RoomRegistry::from_toml(SITE).unwrap();
+        let rooms = registry.default_site().rooms();
+        let short_names = |filter: RoomFilter| -> Vec<String> {
+            filter
+                .apply(&rooms)
+                .iter()
+                .map(|x| x.short_name())
+                .collect()
+        };
+
+        assert_eq!(short_names(RoomFilter::default()), ["A1", "A2", "B0"]);
+        // B0's capacity is unknown
+        assert_eq!(
+            short_names(RoomFilter {
+                min_capacity: Some(20),
+                features: vec![],
+            }),
+            ["A1", "A2"]
+        );
+        assert_eq!(
+            short_names(RoomFilter {
+                min_capacity: Some(20),
+                features: vec!["projector".to_string(), "wheelchair-access".to_string()],
+            }),
+            ["A1"]
+        );
+    }
+
+    #[test]
+    fn filters_only_accept_the_features_of_the_site() {
+        let registry = RoomRegistry::from_toml(SITE).unwrap();
+        let site = registry.default_site();
+        let filter = |features: &[&str]| RoomFilter {
+            min_capacity: None,
+            features: features.iter().map(|x| x.to_string()).collect(),
+        };
+
+        assert_eq!(
+            site.features(),
+            ["projector", "wheelchair-access", "whiteboard"]
+        );
+        assert!(filter(&["PROJECTOR", "wheelchair-access"])
+            .check(&site)
+            .is_ok());
+        let error = filter(&["projector", "projecteur"])
+            .check(&site)
+            .unwrap_err();
+        assert!(error.contains("projecteur"));
+        assert!(error.contains("projector, wheelchair-access, whiteboard"));
+    }
+
+    #[test]
+    fn shipped_catalogue_is_valid() {
+        let registry = RoomRegistry::from_toml(include_str!("../../rooms.toml")).unwrap();
+        let distances = registry.default_site().distances_from("Zik").unwrap();
+
+        assert_eq!(distances["TD06"], 1);
+        assert_eq!(distances["TD17"], 1);
     }
 }
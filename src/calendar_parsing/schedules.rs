@@ -0,0 +1,330 @@
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use icalendar::{
+    Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, Event, EventLike,
+};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    calendar_parsing::{
+        intervals::Interval,
+        rooms::{Room, Site},
+    },
+    networking::ade_api_handling::{
+        get_free_rooms_calendar_list, DataStatus, RoomCalendar, TimeWindow,
+    },
+    networking::calendar_source::CalendarStore,
+};
+
+#[derive(Clone, Debug)]
+pub struct RoomEvent {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+impl RoomEvent {
+    pub fn interval(&self) -> Interval {
+        Interval {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// A room along with its events, parsed once from its ADE calendar and sorted by start time
+#[derive(Clone, Debug)]
+pub struct RoomSchedule {
+    pub room: Room,
+    pub events: Vec<RoomEvent>,
+    /// A missing room has no events, but it can't be considered free either
+    pub status: DataStatus,
+}
+
+impl RoomSchedule {
+    // times without a UTC offset (floating times and all-day events) are read in `timezone`
+    pub fn parse(room: Room, calendar_file: &str, timezone: Tz) -> Self {
+        let (mut events, skipped) = parse_cal_to_events(calendar_file, timezone);
+        events.sort_by_key(|x| x.start);
+
+        if skipped > 0 {
+            tracing::warn!(
+                "Skipped {skipped} unreadable events out of {} in the calendar of {}",
+                skipped + events.len(),
+                room.short_name()
+            );
+        }
+
+        Self {
+            room,
+            events,
+            status: DataStatus::Fresh,
+        }
+    }
+
+    pub fn empty(room: Room) -> Self {
+        Self {
+            room,
+            events: Vec::new(),
+            status: DataStatus::Fresh,
+        }
+    }
+
+    pub fn missing(room: Room) -> Self {
+        Self {
+            status: DataStatus::Missing,
+            ..Self::empty(room)
+        }
+    }
+
+    pub fn is_missing(&self) -> bool {
+        self.status == DataStatus::Missing
+    }
+}
+
+// times skipped when the clocks go forward are shifted by an hour, and ambiguous ones
+// (when the clocks go back) use the earliest possibility
+pub fn local_to_utc(date_time: &NaiveDateTime, timezone: Tz) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(date_time)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(*date_time + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|x| x.with_timezone(&Utc))
+}
+
+// converts any form of DTSTART/DTEND to UTC. A date (all-day event) becomes the midnight starting that day
+fn to_utc(value: DatePerhapsTime, timezone: Tz) -> Result<DateTime<Utc>, String> {
+    let converted = match value {
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(date_time)) => Some(date_time),
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)) => {
+            local_to_utc(&date_time, timezone)
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, tzid }) => {
+            let event_timezone: Tz = tzid
+                .parse()
+                .map_err(|_| format!("unknown timezone {tzid}"))?;
+            local_to_utc(&date_time, event_timezone)
+        }
+        DatePerhapsTime::Date(date) => local_to_utc(&date.and_time(NaiveTime::MIN), timezone),
+    };
+
+    converted.ok_or_else(|| "time out of range".to_string())
+}
+
+fn event_to_room_event(event: &Event, timezone: Tz) -> Result<RoomEvent, String> {
+    let start_value = event.get_start().ok_or("no start time")?;
+    let all_day = matches!(start_value, DatePerhapsTime::Date(_));
+    let start = to_utc(start_value, timezone)?;
+
+    let end = match event.get_end() {
+        Some(end_value) => to_utc(end_value, timezone)?,
+        // an all-day event without an end lasts the whole day
+        None if all_day => to_utc(
+            DatePerhapsTime::Date(start.with_timezone(&timezone).date_naive() + Duration::days(1)),
+            timezone,
+        )?,
+        None => return Err("no end time".to_string()),
+    };
+
+    if end <= start {
+        return Err("ends before it starts".to_string());
+    }
+
+    Ok(RoomEvent {
+        start,
+        end,
+        summary: event.get_summary().unwrap_or("").to_string(),
+        description: event.get_description().map(|x| x.to_string()),
+        location: event.get_location().map(|x| x.to_string()),
+    })
+}
+
+// returns the events of the calendar, along with the number of events that had to be skipped
+fn parse_cal_to_events(calendar_file: &str, timezone: Tz) -> (Vec<RoomEvent>, usize) {
+    let mut events: Vec<RoomEvent> = Vec::new();
+    let mut skipped = 0;
+
+    let cal: Calendar = match calendar_file.parse() {
+        Ok(cal_) => cal_,
+        Err(_) => {
+            tracing::warn!("Failed to parse calendar file. Skipping...");
+            return (events, skipped);
+        }
+    };
+
+    for component in &cal.components {
+        let event: &Event = if let CalendarComponent::Event(evt) = component {
+            evt
+        } else {
+            continue;
+        };
+
+        match event_to_room_event(event, timezone) {
+            Ok(room_event) => events.push(room_event),
+            Err(reason) => {
+                tracing::debug!(
+                    "Skipping event {}: {reason}",
+                    event.get_summary().unwrap_or("")
+                );
+                skipped += 1;
+            }
+        }
+    }
+
+    (events, skipped)
+}
+
+// downloads (or reads from the cache) the calendar of every room and parses it once.
+// rooms whose calendar couldn't be fetched at all get a missing schedule, and the events outside
+// the window are dropped (some sources give whole calendars)
+pub async fn get_room_schedules(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    calendar_list: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Vec<RoomSchedule> {
+    let timezone = site.timezone();
+    let bounds = window.interval(timezone);
+    let mut calendars: HashMap<u32, RoomCalendar> =
+        match get_free_rooms_calendar_list(store, site, calendar_list.clone(), window).await {
+            Ok(_list) => _list.into_iter().map(|x| (x.room.id(), x)).collect(),
+            Err(_) => {
+                tracing::error!("Failed to get the room calendars");
+                HashMap::new()
+            }
+        };
+
+    calendar_list
+        .iter()
+        .map(|room| match calendars.remove(&room.id()) {
+            Some(RoomCalendar {
+                data: Some(calendar_file),
+                status,
+                ..
+            }) => {
+                let mut schedule = RoomSchedule::parse(room.clone(), &calendar_file, timezone);
+                schedule.events.retain(|x| x.interval().overlaps(&bounds));
+                RoomSchedule { status, ..schedule }
+            }
+            _ => RoomSchedule::missing(room.clone()),
+        })
+        .collect()
+}
+
+/// Goes through the slots in order along with all the events of the schedules in a single pass,
+/// and calls `f` on every slot with the events that overlap it (and the index of their schedule).
+/// The slots have to be sorted and must not overlap each other.
+pub fn sweep<'a, T>(
+    schedules: &'a [RoomSchedule],
+    slots: &[Interval],
+    mut f: impl FnMut(&Interval, &[(usize, &'a RoomEvent)]) -> T,
+) -> Vec<T> {
+    let mut events: Vec<(usize, &RoomEvent)> = schedules
+        .iter()
+        .enumerate()
+        .flat_map(|(index, schedule)| schedule.events.iter().map(move |x| (index, x)))
+        .collect();
+    events.sort_by_key(|(_, x)| x.start);
+
+    let mut next_event = 0;
+    let mut active: Vec<(usize, &RoomEvent)> = Vec::new();
+
+    slots
+        .iter()
+        .map(|slot| {
+            // events that start before the end of the slot...
+            while next_event < events.len() && events[next_event].1.start < slot.end {
+                active.push(events[next_event]);
+                next_event += 1;
+            }
+            // ...and don't end before its start
+            active.retain(|(_, x)| x.end > slot.start);
+
+            let overlapping: Vec<(usize, &RoomEvent)> = active
+                .iter()
+                .filter(|(_, x)| x.interval().overlaps(slot))
+                .copied()
+                .collect();
+
+            f(slot, &overlapping)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(events: &[&str]) -> String {
+        let events: String = events
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                format!("BEGIN:VEVENT\r\nUID:{i}\r\nSUMMARY:Event {i}\r\n{x}\r\nEND:VEVENT\r\n")
+            })
+            .collect();
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:test\r\n{events}END:VCALENDAR\r\n")
+    }
+
+    fn utc(date_time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date_time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn every_time_form_is_converted_to_utc() {
+        let cal = calendar(&[
+            "DTSTART:20241021T080000Z\r\nDTEND:20241021T100000Z",
+            "DTSTART;TZID=Europe/Paris:20241021T140000\r\nDTEND;TZID=Europe/Paris:20241021T160000",
+            "DTSTART:20240115T080000\r\nDTEND:20240115T093000",
+        ]);
+        let (events, skipped) = parse_cal_to_events(&cal, chrono_tz::Europe::Paris);
+
+        assert_eq!(skipped, 0);
+        assert_eq!(events[0].start, utc("2024-10-21T08:00:00Z"));
+        assert_eq!(events[0].end, utc("2024-10-21T10:00:00Z"));
+        // summer time
+        assert_eq!(events[1].start, utc("2024-10-21T12:00:00Z"));
+        assert_eq!(events[1].end, utc("2024-10-21T14:00:00Z"));
+        // floating time, read in the site's timezone (winter time)
+        assert_eq!(events[2].start, utc("2024-01-15T07:00:00Z"));
+        assert_eq!(events[2].end, utc("2024-01-15T08:30:00Z"));
+    }
+
+    #[test]
+    fn all_day_events_block_the_whole_day() {
+        let cal = calendar(&[
+            "DTSTART;VALUE=DATE:20241101\r\nDTEND;VALUE=DATE:20241102",
+            "DTSTART;VALUE=DATE:20241225",
+        ]);
+        let (events, skipped) = parse_cal_to_events(&cal, chrono_tz::Europe::Paris);
+
+        assert_eq!(skipped, 0);
+        assert_eq!(events[0].start, utc("2024-11-01T00:00:00+01:00"));
+        assert_eq!(events[0].end, utc("2024-11-02T00:00:00+01:00"));
+        assert_eq!(events[1].start, utc("2024-12-25T00:00:00+01:00"));
+        assert_eq!(events[1].end, utc("2024-12-26T00:00:00+01:00"));
+    }
+
+    #[test]
+    fn unreadable_events_are_counted() {
+        let cal = calendar(&[
+            "DTSTART:20241021T080000Z\r\nDTEND:20241021T100000Z",
+            "DTSTART;TZID=Mars/Olympus_Mons:20241021T140000\r\nDTEND;TZID=Mars/Olympus_Mons:20241021T160000",
+            "DTSTART:20241021T080000Z",
+            "DTEND:20241021T080000Z",
+            "DTSTART:20241021T100000Z\r\nDTEND:20241021T080000Z",
+        ]);
+        let (events, skipped) = parse_cal_to_events(&cal, chrono_tz::Europe::Paris);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(skipped, 4);
+    }
+}
@@ -0,0 +1,238 @@
+use chrono::{DateTime, Local, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calendar_parsing::{
+        intervals::Interval, parsing::AllowedActivities, rooms::Room, schedules::RoomSchedule,
+        zik_policy::ZikLevel,
+    },
+    networking::ade_api_handling::DataStatus,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RoomInfo {
+    pub short_name: String,
+    pub name: Option<String>,
+    pub id: u32,
+    pub capacity: Option<u32>,
+    pub equipment: Vec<String>,
+    pub wheelchair_accessible: bool,
+}
+
+impl From<&Room> for RoomInfo {
+    fn from(room: &Room) -> Self {
+        Self {
+            short_name: room.short_name(),
+            name: room.name(),
+            id: room.id(),
+            capacity: room.capacity(),
+            equipment: room.equipment(),
+            wheelchair_accessible: room.wheelchair_accessible(),
+        }
+    }
+}
+
+impl RoomInfo {
+    // e.g. "30 places, projector, accès PMR", or None if nothing is known about the room
+    pub fn details(&self) -> Option<String> {
+        let details: Vec<String> = self
+            .capacity
+            .map(|x| format!("{x} places"))
+            .into_iter()
+            .chain(self.equipment.iter().cloned())
+            .chain(self.wheelchair_accessible.then(|| "accès PMR".to_string()))
+            .collect();
+
+        if details.is_empty() {
+            return None;
+        }
+        Some(details.join(", "))
+    }
+}
+
+/// A room whose calendar couldn't be refreshed, the last one fetched is used instead
+#[derive(Clone, Debug, Serialize)]
+pub struct StaleRoom {
+    #[serde(flatten)]
+    pub room: RoomInfo,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// The rooms ADE couldn't give an up to date calendar for. Missing rooms are never listed as free
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DataNotice {
+    pub stale_rooms: Vec<StaleRoom>,
+    pub missing_rooms: Vec<RoomInfo>,
+}
+
+impl DataNotice {
+    pub fn from_schedules(schedules: &[RoomSchedule]) -> Self {
+        let mut notice = Self::default();
+
+        for schedule in schedules {
+            notice.add(&schedule.room, schedule.status);
+        }
+
+        notice
+    }
+
+    pub fn add(&mut self, room: &Room, status: DataStatus) {
+        match status {
+            DataStatus::Fresh => {}
+            DataStatus::Stale(fetched_at) => self.stale_rooms.push(StaleRoom {
+                room: room.into(),
+                fetched_at,
+            }),
+            DataStatus::Missing => self.missing_rooms.push(room.into()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stale_rooms.is_empty() && self.missing_rooms.is_empty()
+    }
+
+    // e.g. "Données périmées : TD06 (21/10 14:05)\nDonnées indisponibles : TD07"
+    pub fn description(&self) -> String {
+        let stale = self
+            .stale_rooms
+            .iter()
+            .map(|x| {
+                format!(
+                    "{} ({})",
+                    x.room.short_name,
+                    x.fetched_at.with_timezone(&Local).format("%d/%m %H:%M")
+                )
+            })
+            .join(", ");
+        let missing = self.missing_rooms.iter().map(|x| &x.short_name).join(", ");
+
+        [
+            ("Données périmées", stale),
+            ("Données indisponibles", missing),
+        ]
+        .into_iter()
+        .filter(|(_, rooms)| !rooms.is_empty())
+        .map(|(label, rooms)| format!("{label} : {rooms}"))
+        .join("\n")
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BusyRoom {
+    #[serde(flatten)]
+    pub room: RoomInfo,
+    /// The summaries of the events taking place in the room during the slot
+    pub courses: Vec<String>,
+}
+
+/// One time slice of the free rooms computation, shared by every output format
+#[derive(Clone, Debug, Serialize)]
+pub struct FreeRoomsSlot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub free_rooms: Vec<RoomInfo>,
+    pub busy_rooms: Vec<BusyRoom>,
+}
+
+impl FreeRoomsSlot {
+    // only the rooms with a display name are listed, like in ADE
+    pub fn free_room_names(&self) -> String {
+        self.free_rooms
+            .iter()
+            .filter_map(|x| x.name.clone())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    // one line per free room with a display name and known attributes
+    pub fn free_room_details(&self) -> Vec<String> {
+        self.free_rooms
+            .iter()
+            .filter_map(|x| Some(format!("{} : {}", x.name.as_ref()?, x.details()?)))
+            .collect()
+    }
+}
+
+/// The slots of the free rooms, with the notice next to them rather than in each slot, so that
+/// it's still there when every room is missing and there are no slots
+#[derive(Clone, Debug, Serialize)]
+pub struct FreeRoomsSlots {
+    pub slots: Vec<FreeRoomsSlot>,
+    #[serde(flatten)]
+    pub notice: DataNotice,
+}
+
+/// A room that is free at the requested time
+#[derive(Clone, Debug, Serialize)]
+pub struct FreeRoomNow {
+    #[serde(flatten)]
+    pub room: RoomInfo,
+    /// The start of the room's next event, if there is one in the fetched time window
+    pub free_until: Option<DateTime<Utc>>,
+    pub free_minutes: Option<i64>,
+    /// The distance to the room asked for with "near", if the room is connected to it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FreeRoomsNow {
+    pub at: DateTime<Utc>,
+    pub free_rooms: Vec<FreeRoomNow>,
+    #[serde(flatten)]
+    pub notice: DataNotice,
+}
+
+/// A room that stays free during the whole requested interval
+#[derive(Clone, Debug, Serialize)]
+pub struct FoundRoom {
+    #[serde(flatten)]
+    pub room: RoomInfo,
+    /// The end of the room's previous event, if there is one in the fetched time window
+    pub free_from: Option<DateTime<Utc>>,
+    /// The start of the room's next event, if there is one in the fetched time window
+    pub free_until: Option<DateTime<Utc>>,
+    /// The length of the whole free stretch around the requested interval
+    pub free_minutes: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FoundRooms {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub rooms: Vec<FoundRoom>,
+    #[serde(flatten)]
+    pub notice: DataNotice,
+}
+
+/// The level allowed in the Zik during a slot, with the exams that forced it. Unlike a
+/// [`ZikLevel`], it doesn't borrow the schedules it was computed from, so it can be cached
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ZikSlot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub allowed_activities: AllowedActivities,
+    /// e.g. "DS Analyse (TD06)", empty if there is no exam
+    pub exams: String,
+}
+
+impl ZikSlot {
+    pub fn interval(&self) -> Interval {
+        Interval {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl From<&(Interval, ZikLevel<'_>)> for ZikSlot {
+    fn from((slot, level): &(Interval, ZikLevel)) -> Self {
+        Self {
+            start: slot.start,
+            end: slot.end,
+            allowed_activities: level.allowed_activities,
+            exams: level.exam_names(),
+        }
+    }
+}
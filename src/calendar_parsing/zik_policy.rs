@@ -0,0 +1,679 @@
+use chrono::{Duration, NaiveTime};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::calendar_parsing::{
+    intervals::{subtract, Interval},
+    parsing::{AllowedActivities, WindowPosition},
+    rooms::{Room, RoomRegistry, Site},
+    schedules::{local_to_utc, RoomEvent, RoomSchedule},
+};
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Volume {
+    Quiet,
+    Loud,
+    LoudAndBattery,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct Level {
+    volume: Volume,
+    window: WindowPosition,
+}
+
+impl From<Level> for AllowedActivities {
+    fn from(level: Level) -> Self {
+        match level.volume {
+            Volume::Quiet => AllowedActivities::QuietPlaying(level.window),
+            Volume::Loud => AllowedActivities::LoudPlaying(level.window),
+            Volume::LoudAndBattery => AllowedActivities::LoudPlayingAndBattery(level.window),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OverrideConfig {
+    from: String,
+    to: String,
+    #[serde(flatten)]
+    level: Level,
+}
+
+#[derive(Deserialize)]
+struct RuleConfig {
+    rooms: Vec<String>,
+    #[serde(flatten)]
+    level: Level,
+    #[serde(default)]
+    overrides: Vec<OverrideConfig>,
+}
+
+// the level of the rooms up to `max_distance` away from the Zik in the building model
+#[derive(Deserialize)]
+struct DistanceConfig {
+    max_distance: Option<u32>,
+    #[serde(flatten)]
+    level: Level,
+    #[serde(default)]
+    overrides: Vec<OverrideConfig>,
+}
+
+#[derive(Default, Deserialize)]
+struct ExamsConfig {
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    rooms: Vec<String>,
+    #[serde(default)]
+    wings: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PolicyConfig {
+    site: Option<String>,
+    default: Level,
+    // the room or place of the building model the Zik is
+    zik: Option<String>,
+    #[serde(default)]
+    distances: Vec<DistanceConfig>,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+    #[serde(default)]
+    exams: ExamsConfig,
+}
+
+// a different level during part of the day. `to` can be before `from`, the override then goes past midnight
+#[derive(Debug)]
+struct Override {
+    from: NaiveTime,
+    to: NaiveTime,
+    level: AllowedActivities,
+}
+
+#[derive(Debug)]
+struct Rule {
+    level: AllowedActivities,
+    overrides: Vec<Override>,
+}
+
+impl Rule {
+    // the strictest of the levels that apply during the slot: the overrides it overlaps,
+    // and the rule's own level if part of the slot isn't covered by any override
+    fn level_during(&self, slot: &Interval, timezone: Tz) -> AllowedActivities {
+        let first_day = slot.start.with_timezone(&timezone).date_naive() - Duration::days(1);
+        let last_day = slot.end.with_timezone(&timezone).date_naive();
+
+        let mut levels: Vec<AllowedActivities> = Vec::new();
+        let mut covered: Vec<Interval> = Vec::new();
+
+        for day in first_day.iter_days().take_while(|x| x <= &last_day) {
+            for x in &self.overrides {
+                let end_day = if x.to <= x.from {
+                    day + Duration::days(1)
+                } else {
+                    day
+                };
+                let (Some(start), Some(end)) = (
+                    local_to_utc(&day.and_time(x.from), timezone),
+                    local_to_utc(&end_day.and_time(x.to), timezone),
+                ) else {
+                    continue;
+                };
+
+                if let Some(overlap) = (Interval { start, end }).clip(slot) {
+                    levels.push(x.level);
+                    covered.push(overlap);
+                }
+            }
+        }
+
+        if !subtract(slot, &covered).is_empty() {
+            levels.push(self.level);
+        }
+
+        levels.into_iter().min().unwrap_or(self.level)
+    }
+}
+
+/// The level allowed in the Zik during a slot, along with the exams that forced it
+pub struct ZikLevel<'a> {
+    pub allowed_activities: AllowedActivities,
+    pub exams: Vec<(&'a Room, &'a RoomEvent)>,
+}
+
+impl ZikLevel<'_> {
+    // e.g. "DS Analyse (TD06), Partiel (TD07)", each exam listed once
+    pub fn exam_names(&self) -> String {
+        self.exams
+            .iter()
+            .map(|(room, event)| format!("{} ({})", event.summary, room.short_name()))
+            .unique()
+            .join(", ")
+    }
+}
+
+/// The noise policy of the Zik: the level allowed when none of its neighbours is busy,
+/// and the level each busy neighbour lowers it to, loaded at startup from the Zik config file
+///
+/// ```
+/// use ade::{
+///     compute_zik_levels, zik_levels_to_calendar, AllowedActivities, DataNotice, RoomRegistry,
+///     RoomSchedule, TimeWindow, WindowPosition, ZikPolicy,
+/// };
+/// use chrono::NaiveDate;
+///
+/// let registry = RoomRegistry::from_toml(
+///     r#"
+///     [[sites]]
+///     name = "enseirb"
+///     ade_url = "https://ade.example.com"
+///     project_id = 1
+///     display_config_id = 71
+///     rooms = [{ short_name = "TD06", id = 3258 }]
+///     "#,
+/// )?;
+/// let policy = ZikPolicy::from_toml(
+///     r#"
+///     default = { volume = "loud-and-battery", window = "open" }
+///
+///     [[rules]]
+///     rooms = ["TD06"]
+///     volume = "quiet"
+///     window = "closed"
+///     "#,
+///     &registry,
+/// )?;
+///
+/// // TD06 is busy from 8:00 to 10:00
+/// let schedules = [RoomSchedule::parse(
+///     policy.rooms()[0].clone(),
+///     "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\n\
+///      DTSTART:20241021T080000Z\r\nDTEND:20241021T100000Z\r\nSUMMARY:Analyse\r\n\
+///      END:VEVENT\r\nEND:VCALENDAR\r\n",
+///     policy.site().timezone(),
+/// )];
+///
+/// let levels = compute_zik_levels(&policy, &schedules);
+/// assert_eq!(levels.len(), 1);
+/// assert_eq!(
+///     levels[0].1.allowed_activities,
+///     AllowedActivities::QuietPlaying(WindowPosition::Closed)
+/// );
+///
+/// let window = TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 1).unwrap();
+/// let calendar = zik_levels_to_calendar(&levels, &DataNotice::default(), window);
+/// assert!(calendar.to_string().contains("SUMMARY:Volume max"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct ZikPolicy {
+    site: Arc<Site>,
+    default: AllowedActivities,
+    // by ADE resource id
+    rules: HashMap<u32, Arc<Rule>>,
+    // each keyword split into words
+    exam_keywords: Vec<Vec<String>>,
+    exam_rooms: HashSet<u32>,
+}
+
+// lowercase words, so that "DS" matches "DS Analyse" or "(ds)" but not "DSP"
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect()
+}
+
+fn parse_time(val: &str) -> Result<NaiveTime, Box<dyn Error>> {
+    NaiveTime::parse_from_str(val, "%H:%M")
+        .map_err(|_| format!("invalid time \"{val}\", expected HH:MM").into())
+}
+
+fn build_rule(level: Level, overrides: &[OverrideConfig]) -> Result<Arc<Rule>, Box<dyn Error>> {
+    let overrides = overrides
+        .iter()
+        .map(|x| {
+            Ok(Override {
+                from: parse_time(&x.from)?,
+                to: parse_time(&x.to)?,
+                level: x.level.into(),
+            })
+        })
+        .collect::<Result<Vec<Override>, Box<dyn Error>>>()?;
+
+    Ok(Arc::new(Rule {
+        level: level.into(),
+        overrides,
+    }))
+}
+
+impl ZikPolicy {
+    pub fn load(path: &Path, registry: &RoomRegistry) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read Zik policy {}: {e}", path.display()))?;
+
+        Self::from_toml(&data, registry)
+            .map_err(|e| format!("invalid Zik policy {}: {e}", path.display()).into())
+    }
+
+    pub fn from_toml(data: &str, registry: &RoomRegistry) -> Result<Self, Box<dyn Error>> {
+        let config: PolicyConfig = toml::from_str(data)?;
+
+        let site = match &config.site {
+            Some(name) => registry
+                .site(name)
+                .ok_or_else(|| format!("unknown site {name}"))?,
+            None => registry.default_site(),
+        };
+
+        let mut rules: HashMap<u32, Arc<Rule>> = HashMap::new();
+        for rule_config in config.rules {
+            let rule = build_rule(rule_config.level, &rule_config.overrides)?;
+
+            for name in rule_config.rooms {
+                let room = site.from_string(name.clone()).ok_or_else(|| {
+                    format!(
+                        "unknown room {name} (not in the catalogue of site {})",
+                        site.name()
+                    )
+                })?;
+                if rules.insert(room.id(), rule.clone()).is_some() {
+                    return Err(format!("room {name} is listed in several rules"))?;
+                }
+            }
+        }
+
+        // the rooms without a rule get the level of their distance to the Zik
+        if !config.distances.is_empty() {
+            let zik = config
+                .zik
+                .ok_or("the levels by distance need the place of the Zik (\"zik\")")?;
+            let distances = site.distances_from(&zik).ok_or_else(|| {
+                format!(
+                    "unknown room or place {zik} (not in the building model of site {})",
+                    site.name()
+                )
+            })?;
+
+            let mut distance_rules = config
+                .distances
+                .iter()
+                .map(|x| Ok((x.max_distance, build_rule(x.level, &x.overrides)?)))
+                .collect::<Result<Vec<(Option<u32>, Arc<Rule>)>, Box<dyn Error>>>()?;
+            distance_rules.sort_by_key(|(max_distance, _)| max_distance.unwrap_or(u32::MAX));
+
+            for room in site.rooms().iter() {
+                let Some(distance) = distances.get(&room.short_name()) else {
+                    continue;
+                };
+                let rule = distance_rules
+                    .iter()
+                    .find(|(max_distance, _)| max_distance.is_none_or(|x| *distance <= x));
+                if let Some((_, rule)) = rule {
+                    rules.entry(room.id()).or_insert_with(|| rule.clone());
+                }
+            }
+        }
+
+        let mut exam_rooms = HashSet::new();
+        for wing in &config.exams.wings {
+            let wing_rooms: Vec<Room> = site
+                .rooms()
+                .iter()
+                .filter(|x| x.wing().as_ref() == Some(wing))
+                .cloned()
+                .collect();
+            if wing_rooms.is_empty() {
+                return Err(format!(
+                    "unknown exam wing {wing} (no room of site {} is in it)",
+                    site.name()
+                ))?;
+            }
+            exam_rooms.extend(wing_rooms.iter().map(|x| x.id()));
+        }
+        for name in config.exams.rooms {
+            let room = site.from_string(name.clone()).ok_or_else(|| {
+                format!(
+                    "unknown exam room {name} (not in the catalogue of site {})",
+                    site.name()
+                )
+            })?;
+            exam_rooms.insert(room.id());
+        }
+
+        let exam_keywords: Vec<Vec<String>> = config
+            .exams
+            .keywords
+            .iter()
+            .map(|x| words(x))
+            .filter(|x| !x.is_empty())
+            .collect();
+
+        Ok(Self {
+            site,
+            default: config.default.into(),
+            rules,
+            exam_keywords,
+            exam_rooms,
+        })
+    }
+
+    pub fn site(&self) -> Arc<Site> {
+        self.site.clone()
+    }
+
+    // the level when none of the rooms is busy
+    pub fn default_level(&self) -> AllowedActivities {
+        self.default
+    }
+
+    // the rooms that have a rule or where exams are looked for, the others never change the level
+    pub fn rooms(&self) -> Arc<Vec<Room>> {
+        Arc::new(
+            self.site
+                .rooms()
+                .iter()
+                .filter(|x| self.rules.contains_key(&x.id()) || self.exam_rooms.contains(&x.id()))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    // an event is an exam if one of the keywords appears in its summary or description
+    fn is_exam(&self, event: &RoomEvent) -> bool {
+        let mut event_words = words(&event.summary);
+        if let Some(description) = &event.description {
+            event_words.extend(words(description));
+        }
+
+        self.exam_keywords.iter().any(|keyword| {
+            event_words
+                .windows(keyword.len())
+                .any(|x| x == keyword.as_slice())
+        })
+    }
+
+    /// The level allowed during the slot: the strictest of the levels of the rooms the overlapping
+    /// events come from, or the strictest level there is if one of them is an exam in the exam
+    /// rooms. The room of the schedule is used rather than the event's LOCATION, which can list
+    /// several rooms.
+    pub fn level<'a>(
+        &self,
+        schedules: &'a [RoomSchedule],
+        slot: &Interval,
+        overlapping_events: &[(usize, &'a RoomEvent)],
+    ) -> ZikLevel<'a> {
+        let exams: Vec<(&'a Room, &'a RoomEvent)> = overlapping_events
+            .iter()
+            .filter(|(index, event)| {
+                self.exam_rooms.contains(&schedules[*index].room.id()) && self.is_exam(event)
+            })
+            .map(|(index, event)| (&schedules[*index].room, *event))
+            .collect();
+
+        if !exams.is_empty() {
+            return ZikLevel {
+                allowed_activities: AllowedActivities::QuietPlaying(WindowPosition::Closed),
+                exams,
+            };
+        }
+
+        let timezone = self.site.timezone();
+        let allowed_activities = overlapping_events
+            .iter()
+            .filter_map(|(index, _)| self.rules.get(&schedules[*index].room.id()))
+            .map(|rule| rule.level_during(slot, timezone))
+            .fold(self.default, |level, x| level.min(x));
+
+        ZikLevel {
+            allowed_activities,
+            exams,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    const CATALOGUE: &str = include_str!("../../rooms.toml");
+
+    fn utc(date_time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date_time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn shipped_policy_is_valid() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = ZikPolicy::from_toml(include_str!("../../zik.toml"), &registry).unwrap();
+
+        assert!(policy.rooms().iter().any(|x| x.short_name() == "TD06"));
+    }
+
+    // the levels of the rooms before the policy was moved to zik.toml
+    #[test]
+    fn shipped_policy_matches_the_original_table() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = ZikPolicy::from_toml(include_str!("../../zik.toml"), &registry).unwrap();
+        let table = [
+            (
+                AllowedActivities::QuietPlaying(WindowPosition::Closed),
+                vec!["TD06", "TD07", "TD17"],
+            ),
+            (
+                AllowedActivities::QuietPlaying(WindowPosition::Open),
+                vec!["TD04", "TD05", "TD08"],
+            ),
+            (
+                AllowedActivities::LoudPlaying(WindowPosition::Closed),
+                vec!["TD09", "TD10", "TD11"],
+            ),
+            (
+                AllowedActivities::LoudPlaying(WindowPosition::Open),
+                vec!["TD12", "TD13", "TD14"],
+            ),
+            (
+                AllowedActivities::LoudPlayingAndBattery(WindowPosition::Closed),
+                vec![
+                    "TD15", "TD20", "TD21", "TD22", "TD23", "TD24", "TD25", "TD26", "TD27", "TD28",
+                ],
+            ),
+        ];
+
+        let site = registry.default_site();
+        for (level, rooms) in &table {
+            for short_name in rooms {
+                let room = site.from_string(short_name.to_string()).unwrap();
+                assert_eq!(
+                    policy.rules.get(&room.id()).map(|x| x.level),
+                    Some(*level),
+                    "{short_name}"
+                );
+            }
+        }
+        // the other rooms never changed the level
+        for room in site.rooms().iter() {
+            if !table
+                .iter()
+                .any(|(_, rooms)| rooms.contains(&room.short_name().as_str()))
+            {
+                assert!(
+                    !policy.rules.contains_key(&room.id()),
+                    "{}",
+                    room.short_name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn levels_follow_the_distance_to_the_zik() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = r#"
+            default = { volume = "loud-and-battery", window = "open" }
+            zik = "Zik"
+
+            [[distances]]
+            max_distance = 1
+            volume = "quiet"
+            window = "closed"
+
+            [[distances]]
+            volume = "loud"
+            window = "open"
+
+            [[rules]]
+            rooms = ["TD07"]
+            volume = "quiet"
+            window = "open"
+        "#;
+        let policy = ZikPolicy::from_toml(policy, &registry).unwrap();
+        let level = |short_name: &str| {
+            let room = registry
+                .default_site()
+                .from_string(short_name.to_string())
+                .unwrap();
+            policy.rules.get(&room.id()).map(|x| x.level)
+        };
+
+        assert_eq!(
+            level("TD06"),
+            Some(AllowedActivities::QuietPlaying(WindowPosition::Closed))
+        );
+        // explicit rules come first
+        assert_eq!(
+            level("TD07"),
+            Some(AllowedActivities::QuietPlaying(WindowPosition::Open))
+        );
+        assert_eq!(
+            level("TD14"),
+            Some(AllowedActivities::LoudPlaying(WindowPosition::Open))
+        );
+        // not connected to the Zik
+        assert_eq!(level("TD25"), None);
+    }
+
+    #[test]
+    fn unknown_rooms_are_reported_at_load_time() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = r#"
+            default = { volume = "loud-and-battery", window = "open" }
+
+            [[rules]]
+            rooms = ["TD06", "TD99"]
+            volume = "quiet"
+            window = "closed"
+        "#;
+
+        let error = ZikPolicy::from_toml(policy, &registry).unwrap_err();
+        assert!(error.to_string().contains("TD99"));
+    }
+
+    fn room_event(summary: &str, description: Option<&str>) -> RoomEvent {
+        RoomEvent {
+            start: utc("2024-10-21T08:00:00Z"),
+            end: utc("2024-10-21T10:00:00Z"),
+            summary: summary.to_string(),
+            description: description.map(|x| x.to_string()),
+            location: None,
+        }
+    }
+
+    #[test]
+    fn exams_are_found_by_keyword() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = ZikPolicy::from_toml(include_str!("../../zik.toml"), &registry).unwrap();
+
+        assert!(policy.is_exam(&room_event("DS Analyse", None)));
+        assert!(policy.is_exam(&room_event("Analyse", Some("\n\nI1 (partiel)\n"))));
+        assert!(policy.is_exam(&room_event("EXAMEN Réseaux", None)));
+        assert!(!policy.is_exam(&room_event("TD DSP", None)));
+        assert!(!policy.is_exam(&room_event("Cours", Some("Préparation aux examens"))));
+    }
+
+    #[test]
+    fn exam_in_the_building_forces_the_strictest_level() {
+        let registry = RoomRegistry::from_toml(CATALOGUE).unwrap();
+        let policy = ZikPolicy::from_toml(include_str!("../../zik.toml"), &registry).unwrap();
+        let site = registry.default_site();
+
+        // TD13 only lowers the level to loud with the window open, and TD25 is in the other building
+        let schedules: Vec<RoomSchedule> = ["TD13", "TD25"]
+            .iter()
+            .map(|x| RoomSchedule {
+                events: vec![room_event("DS Analyse", None)],
+                ..RoomSchedule::empty(site.from_string(x.to_string()).unwrap())
+            })
+            .collect();
+        let slot = schedules[0].events[0].interval();
+
+        let level = policy.level(&schedules, &slot, &[(0, &schedules[0].events[0])]);
+        assert_eq!(
+            level.allowed_activities,
+            AllowedActivities::QuietPlaying(WindowPosition::Closed)
+        );
+        assert_eq!(level.exams.len(), 1);
+
+        let level = policy.level(&schedules, &slot, &[(1, &schedules[1].events[0])]);
+        assert_eq!(
+            level.allowed_activities,
+            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Closed)
+        );
+        assert!(level.exams.is_empty());
+    }
+
+    #[test]
+    fn overrides_apply_during_their_time_range() {
+        let rule = Rule {
+            level: AllowedActivities::QuietPlaying(WindowPosition::Open),
+            overrides: vec![Override {
+                from: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+                to: NaiveTime::from_hms_opt(14, 0, 0).unwrap(),
+                level: AllowedActivities::LoudPlaying(WindowPosition::Closed),
+            }],
+        };
+        let timezone = chrono_tz::Europe::Paris;
+        let slot = |start: &str, end: &str| Interval {
+            start: utc(start),
+            end: utc(end),
+        };
+
+        // 12:30 to 13:30 in Paris
+        assert_eq!(
+            rule.level_during(
+                &slot("2024-10-21T10:30:00Z", "2024-10-21T11:30:00Z"),
+                timezone
+            ),
+            AllowedActivities::LoudPlaying(WindowPosition::Closed)
+        );
+        // 9:00 to 11:00
+        assert_eq!(
+            rule.level_during(
+                &slot("2024-10-21T07:00:00Z", "2024-10-21T09:00:00Z"),
+                timezone
+            ),
+            AllowedActivities::QuietPlaying(WindowPosition::Open)
+        );
+        // 11:00 to 13:00, partly overridden so the strictest level wins
+        assert_eq!(
+            rule.level_during(
+                &slot("2024-10-21T09:00:00Z", "2024-10-21T11:00:00Z"),
+                timezone
+            ),
+            AllowedActivities::QuietPlaying(WindowPosition::Open)
+        );
+    }
+}
@@ -1,8 +1,33 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 /// An API to find empty classrooms
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+pub struct Cli {
+    /// Without a subcommand, the API is served with the default arguments
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serves the API
+    Serve(Args),
+    /// Lists the free rooms of a day, or the rooms free at a given time
+    Free(FreeArgs),
+    /// Lists the levels allowed in the Zik
+    Zik(ZikArgs),
+    /// Writes the free rooms or Zik calendar to a file
+    Export(ExportArgs),
+    /// Writes the configured free rooms calendars, the Zik calendar and an HTML index to a directory
+    Generate(GenerateArgs),
+    /// Walks the resource tree of an ADE project and writes a room catalogue file
+    Discover(DiscoverArgs),
+}
+
+// also a parser of its own, to serve with the default arguments when there's no subcommand
+#[derive(Parser, Debug)]
 pub struct Args {
     /// The port on which to expose the API (default 7878)
     #[arg(short, long, default_value_t = 7878)]
@@ -17,4 +42,227 @@ pub struct Args {
     /// Measured in weeks
     #[arg(short, long, default_value_t = 2)]
     pub zik_timespan: u16,
+
+    /// The maximum time period a request can ask for with the days, start and end URL parameters
+    /// Measured in days
+    #[arg(short, long, default_value_t = 31)]
+    pub max_days: u16,
+
+    /// How far in the past a request can ask for with the start and at URL parameters
+    /// Measured in days
+    #[arg(long, default_value_t = 7)]
+    pub max_days_back: u16,
+
+    /// How far in the future a request can ask for with the start and at URL parameters
+    /// Measured in days
+    #[arg(long, default_value_t = 365)]
+    pub max_days_ahead: u16,
+
+    /// How often the room calendars are refreshed in the background during working hours, 0 disables it
+    /// Measured in minutes, keep it under the raw and computed TTLs
+    #[arg(long, default_value_t = 10)]
+    pub refresh_interval: u32,
+
+    /// How often the room calendars are refreshed in the background at night and during weekends
+    /// Measured in minutes
+    #[arg(long, default_value_t = 45)]
+    pub idle_refresh_interval: u32,
+
+    /// The hour the working hours start at (in the timezone of the default site, Monday to Friday)
+    #[arg(long, default_value_t = 7)]
+    pub working_hours_start: u32,
+
+    /// The hour the working hours end at (in the timezone of the default site, Monday to Friday)
+    #[arg(long, default_value_t = 20)]
+    pub working_hours_end: u32,
+
+    #[command(flatten)]
+    pub data: DataArgs,
+}
+
+/// Where the rooms, the Zik policy and the calendars come from, shared by the subcommands
+#[derive(ClapArgs, Debug)]
+pub struct DataArgs {
+    /// The TOML file listing the rooms (short name, display name, ADE resource id)
+    #[arg(short, long, default_value = "rooms.toml")]
+    pub rooms_config: PathBuf,
+
+    /// The TOML file describing the noise policy of the Zik (which busy rooms lower the allowed level)
+    #[arg(long, default_value = "zik.toml")]
+    pub zik_config: PathBuf,
+
+    /// Read the room calendars from the .ics files of this directory (named after the short names of
+    /// the rooms, optionally in a directory per site) instead of downloading them from ADE
+    #[arg(long)]
+    pub calendar_dir: Option<PathBuf>,
+
+    /// The directory the cached calendars are saved to, to survive restarts
+    #[arg(long, default_value = "cache")]
+    pub cache_dir: PathBuf,
+
+    /// Keep the cached calendars in memory only, instead of also saving them to the cache directory
+    #[arg(long)]
+    pub no_disk_cache: bool,
+
+    /// How long a calendar downloaded from ADE stays fresh in the cache
+    /// Measured in minutes
+    #[arg(long, default_value_t = 60)]
+    pub raw_ttl: u32,
+
+    /// How long a computed calendar (free rooms, Zik) stays fresh in the cache
+    /// Measured in minutes
+    #[arg(long, default_value_t = 60)]
+    pub computed_ttl: u32,
+
+    /// The maximum size of the cache, 0 for no limit. The calendars nobody asked for in the
+    /// longest time are dropped first, the last one downloaded from ADE for each room is always kept
+    /// Measured in megabytes
+    #[arg(long, default_value_t = 100)]
+    pub max_cache_size: u32,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct FreeArgs {
+    /// The site of the rooms (the first site of the room catalogue by default)
+    #[arg(long)]
+    pub site: Option<String>,
+
+    /// The rooms to look at (short names or aliases separated by commas), all the rooms of the site by default
+    #[arg(long)]
+    pub rooms: Option<String>,
+
+    /// The day: today, tomorrow or YYYY-MM-DD
+    #[arg(long, default_value = "today")]
+    pub day: String,
+
+    /// Only list the rooms free at this time of the day (HH:MM, local time) instead of every slot
+    #[arg(long)]
+    pub at: Option<String>,
+
+    #[command(flatten)]
+    pub data: DataArgs,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct ZikArgs {
+    /// The first day: today, tomorrow or YYYY-MM-DD
+    #[arg(long, default_value = "today")]
+    pub day: String,
+
+    /// The number of days listed
+    #[arg(long, default_value_t = 1)]
+    pub days: u16,
+
+    #[command(flatten)]
+    pub data: DataArgs,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportedCalendar {
+    FreeRooms,
+    Zik,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    Ics,
+    Json,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct ExportArgs {
+    /// The calendar to write
+    #[arg(long, value_enum, default_value_t = ExportedCalendar::FreeRooms)]
+    pub calendar: ExportedCalendar,
+
+    /// The format of the file, JSON is only available for the free rooms
+    #[arg(long, value_enum, default_value_t = ExportFormat::Ics)]
+    pub format: ExportFormat,
+
+    /// The file to write
+    #[arg(short, long)]
+    pub out: PathBuf,
+
+    /// The site of the rooms (the first site of the room catalogue by default)
+    #[arg(long)]
+    pub site: Option<String>,
+
+    /// The rooms to look at (short names or aliases separated by commas), all the rooms of the site by default
+    #[arg(long)]
+    pub rooms: Option<String>,
+
+    /// The first day: today, tomorrow or YYYY-MM-DD
+    #[arg(long, default_value = "today")]
+    pub day: String,
+
+    /// The number of days covered
+    #[arg(long, default_value_t = 1)]
+    pub days: u16,
+
+    #[command(flatten)]
+    pub data: DataArgs,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct GenerateArgs {
+    /// The TOML file listing the free rooms calendars to write (name, title and rooms of each)
+    #[arg(long, default_value = "calendars.toml")]
+    pub calendars_config: PathBuf,
+
+    /// The directory the calendars and the index are written to
+    #[arg(short, long, default_value = "public")]
+    pub out_dir: PathBuf,
+
+    /// The time period covered by the free rooms calendars
+    /// Measured in days
+    #[arg(long, default_value_t = 7)]
+    pub free_rooms_timespan: u16,
+
+    /// The time period covered by the Zik calendar
+    /// Measured in weeks
+    #[arg(long, default_value_t = 2)]
+    pub zik_timespan: u16,
+
+    #[command(flatten)]
+    pub data: DataArgs,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct DiscoverArgs {
+    /// The base URL of the ADE instance
+    #[arg(long)]
+    pub ade_url: String,
+
+    /// The ADE project to walk
+    #[arg(long, default_value_t = 1)]
+    pub project_id: u32,
+
+    /// The display config id written to the catalogue, used to export the calendars
+    #[arg(long, default_value_t = 71)]
+    pub display_config_id: u32,
+
+    /// The name of the site written to the catalogue
+    #[arg(long)]
+    pub site: String,
+
+    /// The login of an ADE web API account
+    #[arg(long)]
+    pub login: String,
+
+    /// The password of the ADE web API account. Prefer the ADE_PASSWORD environment variable:
+    /// a password given on the command line shows up in the process list and the shell history
+    #[arg(long, env = "ADE_PASSWORD", hide_env_values = true)]
+    pub password: String,
+
+    /// The category of resources to walk
+    #[arg(long, default_value = "classroom")]
+    pub category: String,
+
+    /// Only keep the rooms below the branch with this name (e.g. the school)
+    #[arg(long)]
+    pub branch: Option<String>,
+
+    /// The catalogue file to write
+    #[arg(short, long, default_value = "rooms.discovered.toml")]
+    pub out: PathBuf,
 }
@@ -1,30 +1,52 @@
-use crate::networking::request_handling::serve;
+use std::sync::Arc;
 
-use crate::caching::cal_caching::{init_cal_last_update_time, init_resource_last_update_time};
+use crate::calendar_parsing::rooms::RoomRegistry;
+use crate::calendar_parsing::zik_policy::ZikPolicy;
+use crate::networking::calendar_source::CalendarStore;
 // NOTE: The ADE cal goes from 6h to 21h
 
-pub async fn serve_free_rooms() {
-    loop {
-        match init_resource_last_update_time() {
-            Ok(_) => {
-                break;
-            }
-            Err(e) => {
-                tracing::error!("Error: couldn't initialize source cache: ({e}).\nRetrying...")
-            }
-        };
-    }
+/// What the server, the background refresh and the subcommands work with
+#[derive(Clone)]
+pub struct AppData {
+    pub registry: Arc<RoomRegistry>,
+    pub zik_policy: Arc<ZikPolicy>,
+    pub store: Arc<CalendarStore>,
+}
 
-    loop {
-        match init_cal_last_update_time() {
-            Ok(_) => {
-                break;
-            }
-            Err(e) => {
-                tracing::error!("Error: couldn't initialize calendar cache: ({e}).\nRetrying...")
-            }
-        };
+impl AppData {
+    pub fn new(registry: RoomRegistry, zik_policy: ZikPolicy, store: CalendarStore) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            zik_policy: Arc::new(zik_policy),
+            store: Arc::new(store),
+        }
     }
+}
 
-    serve().await; // TODO: caching instead
+/// The time windows the server computes by default and accepts in the requests.
+/// The default values are the ones of the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// The number of days of the free rooms calendars without a time window in the request
+    pub free_rooms_timespan: u16,
+    /// The number of weeks of the Zik calendar without a time window in the request
+    pub zik_timespan: u16,
+    /// The maximum number of days a request can ask for
+    pub max_days: u16,
+    /// How far in the past a request can start, in days
+    pub max_days_back: u16,
+    /// How far in the future a request can start, in days
+    pub max_days_ahead: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            free_rooms_timespan: 1,
+            zik_timespan: 2,
+            max_days: 31,
+            max_days_back: 7,
+            max_days_ahead: 365,
+        }
+    }
 }
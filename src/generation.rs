@@ -0,0 +1,381 @@
+use chrono::prelude::*;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    calendar_parsing::{
+        parsing::{get_free_rooms_calendar, get_zik_calendar},
+        rooms::{canonical_selection, Room, RoomFilter, RoomRegistry, Site},
+    },
+    cli_params::arg_parsing::GenerateArgs,
+    free_rooms::AppData,
+    html::{escape, page},
+    networking::ade_api_handling::TimeWindow,
+};
+
+#[derive(Deserialize)]
+struct SelectionConfig {
+    name: String,
+    title: Option<String>,
+    site: Option<String>,
+    #[serde(default)]
+    rooms: Vec<String>,
+    min_capacity: Option<u32>,
+    #[serde(default)]
+    has: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CalendarsConfig {
+    #[serde(default = "default_zik")]
+    zik: bool,
+    #[serde(default)]
+    calendars: Vec<SelectionConfig>,
+}
+
+fn default_zik() -> bool {
+    true
+}
+
+/// A free rooms calendar written by the generate subcommand
+#[derive(Debug)]
+pub struct CalendarSelection {
+    name: String,
+    title: String,
+    site: Arc<Site>,
+    rooms: Arc<Vec<Room>>,
+}
+
+impl CalendarSelection {
+    pub fn file_name(&self) -> String {
+        format!("{}.ics", self.name)
+    }
+}
+
+/// The calendars written by the generate subcommand, loaded from the calendars config file
+#[derive(Debug)]
+pub struct StaticCalendars {
+    selections: Vec<CalendarSelection>,
+    zik: bool,
+}
+
+impl StaticCalendars {
+    pub fn load(path: &Path, registry: &RoomRegistry) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read calendars config {}: {e}", path.display()))?;
+
+        Self::from_toml(&data, registry)
+            .map_err(|e| format!("invalid calendars config {}: {e}", path.display()).into())
+    }
+
+    pub fn from_toml(data: &str, registry: &RoomRegistry) -> Result<Self, Box<dyn Error>> {
+        let config: CalendarsConfig = toml::from_str(data)?;
+
+        let mut names = HashSet::new();
+        if config.zik {
+            names.insert("zik".to_string());
+        }
+
+        let mut selections = Vec::new();
+        for selection in config.calendars {
+            // the name is used as a file name
+            if selection.name.is_empty()
+                || !selection
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!(
+                    "calendar name \"{}\" must only contain letters, digits, '-' and '_'",
+                    selection.name
+                ))?;
+            }
+            if !names.insert(selection.name.to_lowercase()) {
+                return Err(format!(
+                    "calendar name {} is already used by another calendar",
+                    selection.name
+                ))?;
+            }
+
+            let site = match &selection.site {
+                Some(name) => registry
+                    .site(name)
+                    .ok_or_else(|| format!("calendar {}: unknown site {name}", selection.name))?,
+                None => registry.default_site(),
+            };
+
+            let rooms = match selection.rooms.is_empty() {
+                true => site.rooms(),
+                false => canonical_selection(
+                    selection
+                        .rooms
+                        .iter()
+                        .map(|name| {
+                            site.from_string(name.clone()).ok_or_else(|| {
+                                format!(
+                                    "calendar {}: unknown room {name} (not in the catalogue of site {})",
+                                    selection.name,
+                                    site.name()
+                                )
+                            })
+                        })
+                        .collect::<Result<Vec<Room>, String>>()?,
+                ),
+            };
+            let filter = RoomFilter {
+                min_capacity: selection.min_capacity,
+                features: selection.has,
+            };
+            filter
+                .check(&site)
+                .map_err(|e| format!("calendar {}: {e}", selection.name))?;
+            let rooms = filter.apply(&rooms);
+            if rooms.is_empty() {
+                return Err(format!("calendar {} has no room", selection.name))?;
+            }
+
+            selections.push(CalendarSelection {
+                title: selection.title.unwrap_or_else(|| selection.name.clone()),
+                name: selection.name,
+                site,
+                rooms,
+            });
+        }
+
+        Ok(Self {
+            selections,
+            zik: config.zik,
+        })
+    }
+}
+
+// written next to the final file then renamed, so that the web server never serves half a file
+fn write_atomically(path: &Path, content: &str) -> Result<(), Box<dyn Error>> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    fs::write(&tmp_path, content)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| format!("couldn't write {}: {e}", path.display()).into())
+}
+
+fn index_page(calendars: &StaticCalendars, free_rooms: TimeWindow, zik: TimeWindow) -> String {
+    let covered = |window: TimeWindow| match window.days() {
+        1 => format!("le {}", window.first_day().format("%d/%m")),
+        _ => format!(
+            "du {} au {}",
+            window.first_day().format("%d/%m"),
+            window.last_day().format("%d/%m")
+        ),
+    };
+
+    let mut items: Vec<String> = calendars
+        .selections
+        .iter()
+        .map(|x| {
+            format!(
+                "<li><a href=\"{}\">{}</a><br><span class=\"muted\">{}</span></li>",
+                escape(&x.file_name()),
+                escape(&x.title),
+                escape(&x.rooms.iter().map(|room| room.short_name()).join(", "))
+            )
+        })
+        .collect();
+    if calendars.zik {
+        items.push(
+            "<li><a href=\"zik.ics\">Zik</a><br><span class=\"muted\">Le volume autorisé au Zik</span></li>"
+                .to_string(),
+        );
+    }
+
+    let zik_covered = match calendars.zik {
+        true => format!(", le Zik {}", covered(zik)),
+        false => String::new(),
+    };
+
+    page(
+        "Salles libres",
+        &format!(
+            "<p>Ajoutez le lien d'un calendrier à votre application de calendrier pour vous y abonner. \
+             Les salles libres couvrent {}{zik_covered}.</p>\n<ul>\n{}\n</ul>\n\
+             <p class=\"muted\">Mis à jour le {}</p>",
+            covered(free_rooms),
+            items.join("\n"),
+            Local::now().format("%d/%m/%Y à %H:%M")
+        ),
+    )
+}
+
+// the windows start on `first_day`, today outside of the tests
+async fn generate_from(
+    data: &AppData,
+    calendars: &StaticCalendars,
+    args: &GenerateArgs,
+    first_day: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&args.out_dir)
+        .map_err(|e| format!("couldn't create {}: {e}", args.out_dir.display()))?;
+
+    let window = |days: u16| {
+        TimeWindow::from_days(first_day, days)
+            .ok_or_else(|| format!("the calendars can't start on {first_day}"))
+    };
+
+    let free_rooms_window = window(args.free_rooms_timespan)?;
+    for selection in &calendars.selections {
+        let calendar = get_free_rooms_calendar(
+            data.store.clone(),
+            selection.site.clone(),
+            selection.rooms.clone(),
+            free_rooms_window,
+        )
+        .await;
+        write_atomically(
+            &args.out_dir.join(selection.file_name()),
+            &calendar.to_string(),
+        )?;
+    }
+
+    let zik_window = window(args.zik_timespan.saturating_mul(7))?;
+    if calendars.zik {
+        let calendar =
+            get_zik_calendar(data.store.clone(), data.zik_policy.clone(), zik_window).await;
+        write_atomically(&args.out_dir.join("zik.ics"), &calendar.to_string())?;
+    }
+
+    write_atomically(
+        &args.out_dir.join("index.html"),
+        &index_page(calendars, free_rooms_window, zik_window),
+    )?;
+
+    tracing::info!(
+        "Wrote {} calendars and the index to {}",
+        calendars.selections.len() + usize::from(calendars.zik),
+        args.out_dir.display()
+    );
+
+    Ok(())
+}
+
+/// Writes the free rooms calendars of the selections, the Zik calendar and an HTML index listing
+/// them to the output directory, e.g. for a static web host
+pub async fn generate(data: &AppData, args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let calendars = StaticCalendars::load(&args.calendars_config, &data.registry)?;
+
+    generate_from(data, &calendars, args, Local::now().date_naive()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        caching::cal_caching::{CacheConfig, CalendarCache},
+        calendar_parsing::zik_policy::ZikPolicy,
+        cli_params::arg_parsing::{Cli, Command},
+        networking::calendar_source::{CalendarStore, DirectorySource},
+    };
+    use clap::Parser;
+
+    fn catalogue() -> RoomRegistry {
+        RoomRegistry::from_toml(include_str!("../rooms.toml")).unwrap()
+    }
+
+    #[test]
+    fn the_example_config_is_valid() {
+        let calendars =
+            StaticCalendars::from_toml(include_str!("../calendars.toml"), &catalogue()).unwrap();
+
+        assert!(calendars.zik);
+        assert_eq!(calendars.selections.len(), 2);
+        assert_eq!(
+            calendars.selections[0].rooms.len(),
+            catalogue().default_site().rooms().len()
+        );
+        assert_eq!(calendars.selections[1].rooms.len(), 6);
+    }
+
+    #[test]
+    fn invalid_selections_are_rejected() {
+        let registry = catalogue();
+        let error = |data: &str| {
+            StaticCalendars::from_toml(data, &registry)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(error("[[calendars]]\nname = \"a\"\nrooms = [\"TD99\"]").contains("unknown room"));
+        assert!(error("[[calendars]]\nname = \"../a\"").contains("must only contain"));
+        assert!(error("[[calendars]]\nname = \"zik\"").contains("already used"));
+        assert!(error("[[calendars]]\nname = \"a\"\nmin_capacity = 10000").contains("no room"));
+        assert!(error("[[calendars]]\nname = \"a\"\nhas = [\"projecteur\"]")
+            .contains("unknown feature projecteur"));
+        assert!(StaticCalendars::from_toml(
+            "zik = false\n[[calendars]]\nname = \"zik\"",
+            &registry
+        )
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn calendars_and_index_are_written_to_the_output_directory() {
+        let out_dir = std::env::temp_dir().join(format!("ade-generate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&out_dir);
+
+        let registry = catalogue();
+        let data = AppData {
+            zik_policy: Arc::new(
+                ZikPolicy::from_toml(include_str!("../zik.toml"), &registry).unwrap(),
+            ),
+            registry: Arc::new(registry),
+            store: Arc::new(CalendarStore::new(
+                DirectorySource::new(PathBuf::from("tests/fixtures")),
+                CalendarCache::in_memory(CacheConfig::default()),
+            )),
+        };
+        let calendars = StaticCalendars::from_toml(
+            "[[calendars]]\nname = \"td\"\ntitle = \"TD <6 & 15>\"\nrooms = [\"6\", \"7\", \"8\", \"15\"]",
+            &data.registry,
+        )
+        .unwrap();
+        let Some(Command::Generate(args)) =
+            Cli::try_parse_from(["ade", "generate", "--out-dir", out_dir.to_str().unwrap()])
+                .unwrap()
+                .command
+        else {
+            panic!("not the generate subcommand");
+        };
+
+        generate_from(
+            &data,
+            &calendars,
+            &args,
+            NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        // more than 3 rooms, so the calendar lists the free rooms rather than the room calendars
+        let td = fs::read_to_string(out_dir.join("td.ics")).unwrap();
+        assert!(td.contains("SUMMARY:Salles Libres"));
+        assert!(fs::read_to_string(out_dir.join("zik.ics"))
+            .unwrap()
+            .contains("SUMMARY:Volume max"));
+
+        let index = fs::read_to_string(out_dir.join("index.html")).unwrap();
+        assert!(index.contains("<a href=\"td.ics\">TD &lt;6 &amp; 15&gt;</a>"));
+        assert!(index.contains("TD06, TD07, TD08, TD15"));
+        assert!(index.contains("<a href=\"zik.ics\">"));
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
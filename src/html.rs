@@ -0,0 +1,338 @@
+// the pages are plain HTML with an inline stylesheet, they don't need any JavaScript
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use itertools::Itertools;
+
+use crate::{
+    calendar_parsing::{
+        intervals::{subtract, Interval},
+        parsing::AllowedActivities,
+        slots::{DataNotice, FreeRoomsSlot, ZikSlot},
+    },
+    networking::ade_api_handling::TimeWindow,
+};
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const STYLE: &str =
+    "body { font-family: sans-serif; margin: 2em auto; max-width: 60em; padding: 0 1em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+.notice { background: #fff3cd; padding: 0.5em 1em; white-space: pre-line; }
+.muted { color: #666; }
+nav a { margin-right: 1em; }";
+
+// `body` is already escaped
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"fr\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>\n{STYLE}\n</style>\n</head>\n<body>\n<h1>{title}</h1>\n{body}\n</body>\n</html>\n",
+        title = escape(title),
+    )
+}
+
+/// The levels of the Zik shown in a timetable, and the level outside of their slots
+pub struct ZikView<'a> {
+    pub slots: &'a [ZikSlot],
+    pub default: AllowedActivities,
+    pub notice: &'a DataNotice,
+}
+
+// e.g. "lundi 21/10"
+fn day_title(day: NaiveDate) -> String {
+    let weekday = match day.weekday() {
+        Weekday::Mon => "lundi",
+        Weekday::Tue => "mardi",
+        Weekday::Wed => "mercredi",
+        Weekday::Thu => "jeudi",
+        Weekday::Fri => "vendredi",
+        Weekday::Sat => "samedi",
+        Weekday::Sun => "dimanche",
+    };
+
+    format!("{weekday} {}", day.format("%d/%m"))
+}
+
+// the days and times are those of the site, like in its calendars
+fn site_day(instant: &DateTime<Utc>, timezone: Tz) -> NaiveDate {
+    instant.with_timezone(&timezone).date_naive()
+}
+
+// the end of a slot that goes past midnight also gets its date
+fn slot_times(slot: &Interval, timezone: Tz) -> String {
+    let start = slot.start.with_timezone(&timezone);
+    let end = slot.end.with_timezone(&timezone);
+
+    match start.date_naive() == end.date_naive() {
+        true => format!("{} – {}", start.format("%H:%M"), end.format("%H:%M")),
+        false => format!("{} – {}", start.format("%H:%M"), end.format("%d/%m %H:%M")),
+    }
+}
+
+// the strictest level during the slot: the levels overlapping it, and the default one
+// if part of the slot isn't covered by any of them
+fn zik_level_during(zik: &ZikView, slot: &Interval) -> AllowedActivities {
+    let overlapping: Vec<&ZikSlot> = zik
+        .slots
+        .iter()
+        .filter(|x| x.interval().overlaps(slot))
+        .collect();
+    let covered: Vec<Interval> = overlapping.iter().map(|x| x.interval()).collect();
+
+    overlapping
+        .iter()
+        .map(|x| x.allowed_activities)
+        .chain((!subtract(slot, &covered).is_empty()).then_some(zik.default))
+        .min()
+        .unwrap_or(zik.default)
+}
+
+fn notice_paragraph(label: &str, notice: &DataNotice) -> Option<String> {
+    if notice.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "<p class=\"notice\">{}{}</p>",
+        escape(label),
+        escape(&notice.description())
+    ))
+}
+
+// a table per day of the window, each row under the day its slot starts on. The cells are already escaped
+fn day_tables(
+    window: TimeWindow,
+    timezone: Tz,
+    headers: &[&str],
+    rows: &[(Interval, Vec<String>)],
+    empty_day: &str,
+) -> String {
+    window
+        .first_day()
+        .iter_days()
+        .take(window.days() as usize)
+        .map(|day| {
+            let day_rows: Vec<String> = rows
+                .iter()
+                .filter(|(slot, _)| site_day(&slot.start, timezone) == day)
+                .map(|(slot, cells)| {
+                    format!(
+                        "<tr><td>{}</td>{}</tr>",
+                        slot_times(slot, timezone),
+                        cells.iter().map(|x| format!("<td>{x}</td>")).join("")
+                    )
+                })
+                .collect();
+
+            let content = match day_rows.is_empty() {
+                true => format!("<p class=\"muted\">{}</p>", escape(empty_day)),
+                false => format!(
+                    "<table>\n<tr><th>Créneau</th>{}</tr>\n{}\n</table>",
+                    headers
+                        .iter()
+                        .map(|x| format!("<th>{}</th>", escape(x)))
+                        .join(""),
+                    day_rows.join("\n")
+                ),
+            };
+
+            format!("<h2>{}</h2>\n{content}", day_title(day))
+        })
+        .join("\n")
+}
+
+/// The free rooms of each slot of the window, day by day, along with the level allowed in the Zik
+/// during the slot if the rooms are on its site. The days and times are those of `timezone`, the
+/// timezone of the site. `navigation` is already escaped.
+pub fn free_rooms_page(
+    title: &str,
+    navigation: &str,
+    window: TimeWindow,
+    timezone: Tz,
+    slots: &[FreeRoomsSlot],
+    notice: &DataNotice,
+    zik: Option<&ZikView>,
+) -> String {
+    let rows: Vec<(Interval, Vec<String>)> = slots
+        .iter()
+        .map(|slot| {
+            let interval = Interval {
+                start: slot.start,
+                end: slot.end,
+            };
+            // the display name shows up when hovering the short name
+            let free_rooms = match slot.free_rooms.is_empty() {
+                true => "<span class=\"muted\">Aucune</span>".to_string(),
+                false => slot
+                    .free_rooms
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "<span title=\"{}\">{}</span>",
+                            escape(x.name.as_deref().unwrap_or_default()),
+                            escape(&x.short_name)
+                        )
+                    })
+                    .join(", "),
+            };
+
+            let mut cells = vec![free_rooms];
+            if let Some(zik) = zik {
+                cells.push(escape(zik_level_during(zik, &interval).description()));
+            }
+            (interval, cells)
+        })
+        .collect();
+
+    let headers: &[&str] = match zik {
+        Some(_) => &["Salles libres", "Zik"],
+        None => &["Salles libres"],
+    };
+
+    let mut body = vec![navigation.to_string()];
+    body.extend(notice_paragraph("", notice));
+    body.extend(zik.and_then(|x| notice_paragraph("Zik : ", x.notice)));
+    body.push(day_tables(
+        window,
+        timezone,
+        headers,
+        &rows,
+        "Aucun cours, toutes les salles sont libres.",
+    ));
+
+    page(title, &body.join("\n"))
+}
+
+/// The level allowed in the Zik during each slot of the window, day by day, with the exams that
+/// forced it. The days and times are those of `timezone`, the timezone of the site of the Zik.
+/// `navigation` is already escaped.
+pub fn zik_page(
+    title: &str,
+    navigation: &str,
+    window: TimeWindow,
+    timezone: Tz,
+    zik: &ZikView,
+) -> String {
+    let rows: Vec<(Interval, Vec<String>)> = zik
+        .slots
+        .iter()
+        .map(|slot| {
+            (
+                slot.interval(),
+                vec![
+                    escape(slot.allowed_activities.description()),
+                    escape(&slot.exams),
+                ],
+            )
+        })
+        .collect();
+
+    let mut body = vec![
+        navigation.to_string(),
+        format!(
+            "<p>En dehors des créneaux listés : {}</p>",
+            escape(zik.default.description())
+        ),
+    ];
+    body.extend(notice_paragraph("", zik.notice));
+    body.push(day_tables(
+        window,
+        timezone,
+        &["Niveau", "Examens"],
+        &rows,
+        "Aucun cours autour du Zik.",
+    ));
+
+    page(title, &body.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar_parsing::parsing::WindowPosition;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 10, 21, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn the_zik_level_of_a_slot_is_the_strictest_one() {
+        let level = |start, end, allowed_activities| ZikSlot {
+            start: at(start),
+            end: at(end),
+            allowed_activities,
+            exams: String::new(),
+        };
+        let slots = [
+            level(8, 10, AllowedActivities::LoudPlaying(WindowPosition::Open)),
+            level(
+                10,
+                12,
+                AllowedActivities::QuietPlaying(WindowPosition::Closed),
+            ),
+        ];
+        let zik = ZikView {
+            slots: &slots,
+            default: AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open),
+            notice: &DataNotice::default(),
+        };
+        let during =
+            |start, end| zik_level_during(&zik, &Interval::new(at(start), at(end)).unwrap());
+
+        assert_eq!(
+            during(8, 9),
+            AllowedActivities::LoudPlaying(WindowPosition::Open)
+        );
+        assert_eq!(
+            during(9, 11),
+            AllowedActivities::QuietPlaying(WindowPosition::Closed)
+        );
+        // partly outside of the levels
+        assert_eq!(
+            during(7, 9),
+            AllowedActivities::LoudPlaying(WindowPosition::Open)
+        );
+        assert_eq!(
+            during(13, 14),
+            AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open)
+        );
+        assert_eq!(escape("<a href=\"x\">"), "&lt;a href=&quot;x&quot;&gt;");
+    }
+
+    #[test]
+    fn the_days_and_times_are_those_of_the_site() {
+        let window =
+            TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 2).unwrap();
+        // 00:30 to 01:30 on Tuesday in Paris, still Monday in UTC
+        let zik = ZikView {
+            slots: &[ZikSlot {
+                start: Utc.with_ymd_and_hms(2024, 10, 21, 22, 30, 0).unwrap(),
+                end: Utc.with_ymd_and_hms(2024, 10, 21, 23, 30, 0).unwrap(),
+                allowed_activities: AllowedActivities::QuietPlaying(WindowPosition::Closed),
+                exams: String::new(),
+            }],
+            default: AllowedActivities::LoudPlayingAndBattery(WindowPosition::Open),
+            notice: &DataNotice::default(),
+        };
+
+        let page = zik_page("Zik", "", window, chrono_tz::Europe::Paris, &zik);
+        let tuesday = page.find("<h2>mardi 22/10</h2>").unwrap();
+        let slot = page.find("<td>00:30 – 01:30</td>").unwrap();
+        assert!(slot > tuesday);
+    }
+}
@@ -0,0 +1,127 @@
+//! Free rooms and Zik calendars, computed from the room calendars published by ADE.
+//!
+//! The pipeline goes from the room catalogue ([`RoomRegistry`]) to the calendars of the rooms
+//! ([`CalendarStore`], fetched from a [`CalendarSource`] through a [`CalendarCache`]), parsed into
+//! [`RoomSchedule`]s, and from the schedules to the free rooms ([`FreeRoomsSlot`], [`FreeRoomNow`],
+//! [`FoundRoom`]) and to the levels allowed in the Zik ([`ZikPolicy`]). The renderers turn the
+//! results into calendars.
+//!
+//! The items re-exported at the root of the crate are its stable API. The modules are public too,
+//! but their layout follows the needs of the server and may change.
+//!
+//! The HTTP server ([`serve`]) and the modules of the command line are behind the `server`
+//! feature, enabled by default. Without it, the crate doesn't depend on axum nor clap.
+//!
+//! # Example
+//!
+//! Which rooms are free on Monday morning, with calendars given in advance instead of fetched
+//! from ADE:
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use ade::{
+//!     get_rooms_free_at, CacheConfig, CalendarCache, CalendarStore, FixtureSource, RoomRegistry,
+//!     TimeWindow,
+//! };
+//! use chrono::{NaiveDate, TimeZone, Utc};
+//!
+//! const TD06: &str = "BEGIN:VCALENDAR\r
+//! VERSION:2.0\r
+//! BEGIN:VEVENT\r
+//! DTSTART:20241021T080000Z\r
+//! DTEND:20241021T100000Z\r
+//! SUMMARY:Analyse\r
+//! END:VEVENT\r
+//! END:VCALENDAR\r
+//! ";
+//! const TD07: &str = "BEGIN:VCALENDAR\r
+//! VERSION:2.0\r
+//! END:VCALENDAR\r
+//! ";
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let registry = RoomRegistry::from_toml(
+//!     r#"
+//!     [[sites]]
+//!     name = "enseirb"
+//!     ade_url = "https://ade.example.com"
+//!     project_id = 1
+//!     display_config_id = 71
+//!     rooms = [
+//!         { short_name = "TD06", id = 3258 },
+//!         { short_name = "TD07", id = 3257 },
+//!     ]
+//!     "#,
+//! )?;
+//! let site = registry.default_site();
+//!
+//! let source = FixtureSource::new()
+//!     .with(&site.from_string("TD06".to_string()).unwrap(), TD06)
+//!     .with(&site.from_string("TD07".to_string()).unwrap(), TD07);
+//! let cache = CalendarCache::in_memory(CacheConfig::default());
+//! let store = Arc::new(CalendarStore::new(source, cache));
+//!
+//! let window = TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 1).unwrap();
+//! let at = Utc.with_ymd_and_hms(2024, 10, 21, 9, 0, 0).unwrap();
+//! let (free_rooms, notice) =
+//!     get_rooms_free_at(store, site.clone(), site.rooms(), window, at).await;
+//!
+//! assert!(notice.is_empty());
+//! assert_eq!(free_rooms.len(), 1);
+//! assert_eq!(free_rooms[0].room.short_name, "TD07");
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod caching;
+pub mod calendar_parsing;
+#[cfg(feature = "server")]
+pub mod cli_params;
+pub mod free_rooms;
+#[cfg(feature = "server")]
+pub mod generation;
+pub mod html;
+pub mod networking;
+#[cfg(feature = "server")]
+pub mod terminal;
+
+// room catalogue
+pub use calendar_parsing::rooms::{canonical_selection, Room, RoomFilter, RoomRegistry, Site};
+
+// calendar fetching
+pub use caching::cal_caching::{CacheConfig, CalendarCache};
+pub use networking::ade_api_handling::{DataStatus, TimeWindow};
+pub use networking::calendar_source::{
+    AdeSource, CalendarSource, CalendarStore, DirectorySource, FixtureSource,
+};
+
+// schedules and free rooms
+pub use calendar_parsing::intervals::Interval;
+pub use calendar_parsing::parsing::{
+    compute_free_rooms_slots, find_free_rooms, get_free_rooms_slots, get_rooms_free_at,
+};
+pub use calendar_parsing::schedules::{get_room_schedules, RoomEvent, RoomSchedule};
+pub use calendar_parsing::slots::{
+    BusyRoom, DataNotice, FoundRoom, FoundRooms, FreeRoomNow, FreeRoomsNow, FreeRoomsSlot,
+    FreeRoomsSlots, RoomInfo, StaleRoom, ZikSlot,
+};
+
+// Zik
+pub use calendar_parsing::parsing::{
+    compute_zik_levels, get_zik_schedules, get_zik_slots, AllowedActivities, WindowPosition,
+};
+pub use calendar_parsing::zik_policy::{ZikLevel, ZikPolicy};
+
+// renderers
+pub use calendar_parsing::parsing::{
+    compute_free_rooms_calendar, compute_zik_calendar, free_rooms_slots_to_calendar,
+    get_free_rooms_calendar, get_zik_calendar, zik_levels_to_calendar,
+};
+
+// server
+pub use caching::refresh::{refresh_calendars, RefreshSchedule};
+pub use free_rooms::{AppData, ServerConfig};
+#[cfg(feature = "server")]
+pub use networking::request_handling::serve;
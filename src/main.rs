@@ -1,20 +1,116 @@
-pub mod caching;
-pub mod calendar_parsing;
-pub mod cli_params;
-pub mod free_rooms;
-pub mod networking;
+use std::error::Error;
 
-use free_rooms::serve_free_rooms;
+use chrono::Duration;
+use clap::Parser;
+
+use ade::cli_params::arg_parsing::{Args, Cli, Command, DataArgs};
+use ade::generation::generate;
+use ade::networking::ade_discovery::discover;
+use ade::terminal::{export_calendar, print_free_rooms, print_zik_levels};
+use ade::{
+    refresh_calendars, serve, AdeSource, AppData, CacheConfig, CalendarCache, CalendarSource,
+    CalendarStore, DirectorySource, RefreshSchedule, RoomRegistry, ServerConfig, ZikPolicy,
+};
 
 // NOTE: The ADE cal goes from 6h to 21h
 
+fn load_data(args: &DataArgs) -> Result<AppData, Box<dyn Error>> {
+    let registry = RoomRegistry::load(&args.rooms_config)
+        .map_err(|e| format!("Failed to load the room catalogue: {e}"))?;
+    let zik_policy = ZikPolicy::load(&args.zik_config, &registry)
+        .map_err(|e| format!("Failed to load the Zik policy: {e}"))?;
+
+    // the app still works without the cache directory, it only loses the cache when restarted
+    let cache_config = CacheConfig::new(
+        Duration::minutes(i64::from(args.raw_ttl)),
+        Duration::minutes(i64::from(args.computed_ttl)),
+        (args.max_cache_size > 0).then(|| u64::from(args.max_cache_size) << 20),
+    );
+    let cache = match args.no_disk_cache {
+        true => CalendarCache::in_memory(cache_config),
+        false => match CalendarCache::persistent(&args.cache_dir, cache_config) {
+            Ok(_cache) => _cache,
+            Err(e) => {
+                tracing::error!(
+                    "Couldn't load the cache directory, keeping the cache in memory only: {e}"
+                );
+                CalendarCache::in_memory(cache_config)
+            }
+        },
+    };
+
+    let source: Box<dyn CalendarSource> = match &args.calendar_dir {
+        Some(dir) => Box::new(DirectorySource::new(dir.clone())),
+        None => Box::new(AdeSource::new()),
+    };
+
+    Ok(AppData::new(
+        registry,
+        zik_policy,
+        CalendarStore { source, cache },
+    ))
+}
+
+async fn run_server(args: Args) -> Result<(), Box<dyn Error>> {
+    let data = load_data(&args.data)?;
+    let config = ServerConfig {
+        free_rooms_timespan: args.free_rooms_timespan,
+        zik_timespan: args.zik_timespan,
+        max_days: args.max_days,
+        max_days_back: args.max_days_back,
+        max_days_ahead: args.max_days_ahead,
+    };
+
+    if let Some(schedule) = RefreshSchedule::new(
+        args.refresh_interval,
+        args.idle_refresh_interval,
+        args.working_hours_start,
+        args.working_hours_end,
+    ) {
+        tokio::spawn(refresh_calendars(data.clone(), config, schedule));
+    }
+
+    serve(data, config, args.port).await;
+
+    Ok(())
+}
+
+async fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Serve(args) => run_server(args).await,
+        Command::Free(args) => print_free_rooms(&load_data(&args.data)?, &args).await,
+        Command::Zik(args) => print_zik_levels(&load_data(&args.data)?, &args).await,
+        Command::Export(args) => export_calendar(&load_data(&args.data)?, &args).await,
+        Command::Generate(args) => generate(&load_data(&args.data)?, &args).await,
+        Command::Discover(args) => discover(args)
+            .await
+            .map_err(|e| format!("Failed to discover the rooms: {e}").into()),
+    }
+}
+
 #[tracing::instrument]
 #[tokio::main]
 async fn main() {
-    let sub = tracing_subscriber::FmtSubscriber::new();
+    // the logs go to stderr, stdout is kept for the output of the subcommands
+    let sub = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .finish();
     match tracing::subscriber::set_global_default(sub) {
-        Ok(_) => {tracing::info!("Successfully set up tracing!")},
-        Err(_) => {eprintln!("Oh no, no tracing :(")},
+        Ok(_) => {
+            tracing::info!("Successfully set up tracing!")
+        }
+        Err(_) => {
+            eprintln!("Oh no, no tracing :(")
+        }
+    }
+
+    // without a subcommand the API is served, like before there were subcommands
+    let command = Cli::parse()
+        .command
+        .unwrap_or_else(|| Command::Serve(Args::parse_from([env!("CARGO_PKG_NAME")])));
+
+    if let Err(e) = run(command).await {
+        tracing::error!("{e}");
+        std::process::exit(1);
     }
-    serve_free_rooms().await;
 }
@@ -1,3 +1,7 @@
 pub mod ade_api_handling;
+#[cfg(feature = "server")]
+pub mod ade_discovery;
+pub mod calendar_source;
+#[cfg(feature = "server")]
 pub mod request_handling;
 // pub mod service;
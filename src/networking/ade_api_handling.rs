@@ -1,124 +1,238 @@
 use crate::{
-    caching::cal_caching::{get_resource_from_cache_file, save_resource_to_cache_file},
-    calendar_parsing::rooms::EnseirbRoom,
+    calendar_parsing::{
+        intervals::Interval,
+        rooms::{Room, Site},
+        schedules::local_to_utc,
+    },
+    networking::calendar_source::CalendarStore,
 };
-use chrono::{prelude::*, Duration};
+use chrono::{prelude::*, Days, Duration};
+use chrono_tz::Tz;
 use futures::{stream, StreamExt};
-use reqwest::Url;
 use std::sync::Arc;
 
 const BATCH_SIZE: usize = 5;
 
+/// How up to date the calendar of a room is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataStatus {
+    Fresh,
+    /// The source (ADE) couldn't be reached, the calendar is the last one fetched (at that time)
+    Stale(DateTime<Utc>),
+    /// The source couldn't be reached and the calendar was never fetched
+    Missing,
+}
+
+/// The calendar of a room, as fetched from the source or read from the cache
+#[derive(Clone, Debug)]
+pub struct RoomCalendar {
+    pub room: Room,
+    /// None if the calendar is missing
+    pub data: Option<String>,
+    pub status: DataStatus,
+}
+
+// returns the calendar of every room, along with how up to date it is
 pub async fn get_free_rooms_calendar_list(
-    resource_ids: Arc<Vec<EnseirbRoom>>,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    resource_ids: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Result<Vec<RoomCalendar>, Box<dyn std::error::Error>> {
     tracing::info!("Downloading...");
-    let return_vec: Vec<String> = fetch_icals_from_urls(resource_ids.clone()).await?;
+    let return_vec: Vec<RoomCalendar> =
+        fetch_calendars(store, site, resource_ids, window, true).await?;
     tracing::info!("Download done!");
 
     Ok(return_vec)
 }
 
-fn get_time_interval() -> (String, String) {
-    let today = format!("{}", Local::now());
-    let tomorrow = format!("{}", Local::now() + Duration::days(3));
-    let first_date = today.split(" ").collect::<Vec<&str>>()[0].to_string();
-    let last_date = tomorrow.split(" ").collect::<Vec<&str>>()[0].to_string();
+// downloads the calendar of every room again even if it's cached, and caches it.
+// returns the number of rooms whose calendar could be fetched
+pub async fn refresh_calendar_list(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    resource_ids: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    Ok(fetch_calendars(store, site, resource_ids, window, false)
+        .await?
+        .iter()
+        .filter(|x| x.status == DataStatus::Fresh)
+        .count())
+}
 
-    (first_date, last_date)
+/// The range of days fetched from ADE, both ends included
+///
+/// ```
+/// use ade::TimeWindow;
+/// use chrono::{NaiveDate, TimeZone, Utc};
+/// use chrono_tz::Europe::Paris;
+///
+/// let monday = NaiveDate::from_ymd_opt(2024, 10, 21).unwrap();
+/// let window = TimeWindow::from_days(monday, 14).unwrap().clamp(7);
+///
+/// assert_eq!(window.days(), 7);
+/// assert_eq!(window.last_date(), "2024-10-27");
+/// // the days go from midnight to midnight in the timezone of the site, whatever the host's,
+/// // and the clocks go back on the 27th
+/// let interval = window.interval(Paris);
+/// assert_eq!(interval.start, Utc.with_ymd_and_hms(2024, 10, 20, 22, 0, 0).unwrap());
+/// assert_eq!(interval.end, Utc.with_ymd_and_hms(2024, 10, 27, 23, 0, 0).unwrap());
+/// assert!(TimeWindow::new(monday, monday.pred_opt().unwrap()).is_none());
+/// assert!(TimeWindow::from_days(NaiveDate::MAX, 2).is_none());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    first_date: NaiveDate,
+    last_date: NaiveDate,
 }
 
-async fn fetch_icals_from_urls(
-    resources: Arc<Vec<EnseirbRoom>>,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let resource_processing = |resource: &EnseirbRoom| {
-            let client = client.clone();
-            let res = resource.clone();
-            tokio::spawn(async move {
-                let id = match res.id() {
-                    Some(_id) => _id,
-                    None => 0,
-                };
-                if let Some(data) = get_resource_from_cache_file(id) {
-                    // TODO: change signature
-                    // NOTE: I have no idea what that todo meant anymore
-                    return Some(data);
-                }
+impl TimeWindow {
+    pub fn new(first_date: NaiveDate, last_date: NaiveDate) -> Option<Self> {
+        if last_date < first_date {
+            return None;
+        }
 
-                let (first_date, last_date) = get_time_interval();
-                let url: String = match res.url(first_date, last_date) {
-                    Some(_url) => _url,
-                    None => "".to_string(),
-                };
+        Some(Self {
+            first_date,
+            last_date,
+        })
+    }
 
-                let the_url = match Url::parse(url.as_str()) {
-                    Ok(_url) => _url,
-                    Err(_) => {
-                        return None;
-                    }
+    // a window of `days` days starting on `first_date`, always at least one day long.
+    // None if it would end after the last date chrono can represent
+    pub fn from_days(first_date: NaiveDate, days: u16) -> Option<Self> {
+        let days = days.max(1);
+        Some(Self {
+            first_date,
+            last_date: first_date.checked_add_days(Days::new(u64::from(days) - 1))?,
+        })
+    }
+
+    pub fn days(&self) -> i64 {
+        (self.last_date - self.first_date).num_days() + 1
+    }
+
+    // shortens the window so that it doesn't cover more than `max_days` days
+    pub fn clamp(self, max_days: u16) -> Self {
+        if self.days() <= i64::from(max_days) {
+            return self;
+        }
+
+        // shorter than the window itself, so it can't overflow
+        Self::from_days(self.first_date, max_days).unwrap_or(self)
+    }
+
+    // from midnight (in the timezone of the site) at the start of the first day to midnight at
+    // the end of the last one
+    pub fn interval(&self, timezone: Tz) -> Interval {
+        Interval {
+            start: midnight(self.first_date, timezone),
+            end: midnight(self.last_date + Duration::days(1), timezone),
+        }
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        self.first_date
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        self.last_date
+    }
+
+    pub fn first_date(&self) -> String {
+        self.first_date.format("%Y-%m-%d").to_string()
+    }
+
+    pub fn last_date(&self) -> String {
+        self.last_date.format("%Y-%m-%d").to_string()
+    }
+
+    pub fn cache_key(&self) -> String {
+        format!("{}_{}", self.first_date(), self.last_date())
+    }
+}
+
+fn midnight(date: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    local_to_utc(&midnight, timezone).unwrap_or_else(|| midnight.and_utc())
+}
+
+// when a room's calendar can't be fetched from the source, the last one fetched is used (and marked
+// as stale) whatever its age and window, and the room is marked as missing if there is none
+async fn fetch_calendars(
+    store: Arc<CalendarStore>,
+    site: Arc<Site>,
+    resources: Arc<Vec<Room>>,
+    window: TimeWindow,
+    use_cache: bool,
+) -> Result<Vec<RoomCalendar>, Box<dyn std::error::Error>> {
+    let resource_processing = |resource: &Room| {
+        let store = store.clone();
+        let site = site.clone();
+        let res = resource.clone();
+        tokio::spawn(async move {
+            let id = res.id();
+            if let Some(data) = use_cache
+                .then(|| store.cache.get_resource(&site.name(), id, window))
+                .flatten()
+            {
+                return RoomCalendar {
+                    room: res,
+                    data: Some(data),
+                    status: DataStatus::Fresh,
                 };
-                let resp = match client.get(the_url).send().await {
-                    Ok(_resp) => _resp,
-                    Err(_) => {
-                        return None;
+            }
+
+            match store.source.fetch(&site, &res, window).await {
+                Ok(ical) => {
+                    store
+                        .cache
+                        .save_resource(&site.name(), id, window, ical.clone());
+
+                    RoomCalendar {
+                        room: res,
+                        data: Some(ical),
+                        status: DataStatus::Fresh,
                     }
-                };
-                let ical: String = match resp.text().await {
-                    Ok(_cal) => _cal,
-                    Err(_) => return None,
-                };
-                let _ = save_resource_to_cache_file(id, ical.clone());
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to fetch the calendar of {}: {e}", res.short_name());
 
-                Some(ical)
-            })
-        };
+                    match store.cache.get_stale_resource(&site.name(), id) {
+                        Some((data, fetched_at)) => RoomCalendar {
+                            room: res,
+                            data: Some(data),
+                            status: DataStatus::Stale(fetched_at),
+                        },
+                        None => RoomCalendar {
+                            room: res,
+                            data: None,
+                            status: DataStatus::Missing,
+                        },
+                    }
+                }
+            }
+        })
+    };
 
     let thing = stream::iter(resources.iter())
         .map(resource_processing)
         .buffer_unordered(BATCH_SIZE);
 
-    let fuck: Vec<Result<Option<String>, _>> = thing.collect().await;
+    let results: Vec<Result<RoomCalendar, _>> = thing.collect().await;
 
-    let retval: Vec<String> = fuck
-        .iter()
+    let retval: Vec<RoomCalendar> = results
+        .into_iter()
         .filter_map(|x| match x {
-            Ok(Some(_thing)) => Some(_thing.clone()),
-            _ => None,
+            Ok(calendar) => Some(calendar),
+            Err(e) => {
+                tracing::error!("A calendar download crashed: {e}");
+                None
+            }
         })
         .collect();
 
     Ok(retval)
 }
-
-pub async fn get_zik_rooms() -> Result<Vec<EnseirbRoom>, reqwest::Error> {
-    let resource_ids: Vec<EnseirbRoom> = vec![
-        EnseirbRoom::TD01,
-        EnseirbRoom::TD02,
-        EnseirbRoom::TD03,
-        EnseirbRoom::TD04,
-        EnseirbRoom::TD05,
-        EnseirbRoom::TD06,
-        EnseirbRoom::TD07,
-        EnseirbRoom::TD08,
-        EnseirbRoom::TD09,
-        EnseirbRoom::TD10,
-        EnseirbRoom::TD11,
-        EnseirbRoom::TD12,
-        EnseirbRoom::TD13,
-        EnseirbRoom::TD14,
-        EnseirbRoom::TD15,
-        EnseirbRoom::TD17,
-        EnseirbRoom::TD20,
-        EnseirbRoom::TD21,
-        EnseirbRoom::TD22,
-        EnseirbRoom::TD23,
-        EnseirbRoom::TD24,
-        EnseirbRoom::TD25,
-        EnseirbRoom::TD26,
-        EnseirbRoom::TD27,
-        EnseirbRoom::TD28,
-    ];
-
-    Ok(resource_ids)
-}
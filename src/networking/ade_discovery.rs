@@ -0,0 +1,369 @@
+use reqwest::Url;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+};
+
+use crate::{calendar_parsing::rooms::RoomRegistry, cli_params::arg_parsing::DiscoverArgs};
+
+/// A room found in ADE's resource tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredRoom {
+    pub name: String,
+    pub id: u32,
+    /// The branch of the tree the room is in
+    pub building: Option<String>,
+}
+
+impl DiscoveredRoom {
+    // ADE names look like "EA-S106/S107 (TD06)": the part between parentheses is the short name
+    pub fn short_name(&self) -> String {
+        self.name
+            .rsplit_once('(')
+            .and_then(|(_, x)| x.strip_suffix(')'))
+            .filter(|x| !x.trim().is_empty())
+            .unwrap_or(&self.name)
+            .trim()
+            .to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct CatalogueRoom {
+    short_name: String,
+    name: String,
+    id: u32,
+    aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wing: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CatalogueSite {
+    name: String,
+    ade_url: String,
+    project_id: u32,
+    display_config_id: u32,
+    rooms: Vec<CatalogueRoom>,
+}
+
+#[derive(Serialize)]
+struct Catalogue {
+    sites: Vec<CatalogueSite>,
+}
+
+// calls a function of ADE's web API and returns its XML answer, unless it's an error
+async fn call_web_api(
+    client: &reqwest::Client,
+    ade_url: &str,
+    params: &[(&str, String)],
+) -> Result<String, Box<dyn Error>> {
+    let url = Url::parse_with_params(
+        &format!("{}/jsp/webapi", ade_url.trim_end_matches('/')),
+        params,
+    )?;
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let document = roxmltree::Document::parse(&body)?;
+    let root = document.root_element();
+    if root.has_tag_name("error") {
+        return Err(format!(
+            "ADE error: {}",
+            root.attribute("name")
+                .or(root.attribute("description"))
+                .unwrap_or("unknown")
+        ))?;
+    }
+
+    Ok(body)
+}
+
+// the leaves of the tree, below the branch called `branch` if there is one
+fn parse_resource_tree(
+    data: &str,
+    branch: Option<&str>,
+) -> Result<Vec<DiscoveredRoom>, Box<dyn Error>> {
+    let document = roxmltree::Document::parse(data)?;
+    let mut rooms: Vec<DiscoveredRoom> = Vec::new();
+    let mut ids = HashSet::new();
+
+    for leaf in document.descendants().filter(|x| x.has_tag_name("leaf")) {
+        let branches: Vec<&str> = leaf
+            .ancestors()
+            .filter(|x| x.has_tag_name("branch"))
+            .filter_map(|x| x.attribute("name"))
+            .collect();
+
+        if let Some(branch) = branch {
+            if !branches.iter().any(|x| x.eq_ignore_ascii_case(branch)) {
+                continue;
+            }
+        }
+
+        let (Some(name), Some(id)) = (
+            leaf.attribute("name"),
+            leaf.attribute("id").and_then(|x| x.parse::<u32>().ok()),
+        ) else {
+            tracing::warn!("Skipping a resource without a name or id");
+            continue;
+        };
+
+        // the same resource can be listed in several branches
+        if id == 0 || !ids.insert(id) {
+            continue;
+        }
+
+        rooms.push(DiscoveredRoom {
+            name: name.to_string(),
+            id,
+            building: branches.first().map(|x| x.to_string()),
+        });
+    }
+
+    Ok(rooms)
+}
+
+/// Walks the resource tree of an ADE project and returns the rooms it contains
+pub async fn discover_rooms(args: &DiscoverArgs) -> Result<Vec<DiscoveredRoom>, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+
+    let session = call_web_api(
+        &client,
+        &args.ade_url,
+        &[
+            ("function", "connect".to_string()),
+            ("login", args.login.clone()),
+            ("password", args.password.clone()),
+        ],
+    )
+    .await?;
+    let session_id = roxmltree::Document::parse(&session)?
+        .root_element()
+        .attribute("id")
+        .ok_or("ADE didn't return a session id")?
+        .to_string();
+
+    call_web_api(
+        &client,
+        &args.ade_url,
+        &[
+            ("sessionId", session_id.clone()),
+            ("function", "setProject".to_string()),
+            ("projectId", args.project_id.to_string()),
+        ],
+    )
+    .await?;
+
+    let resources = call_web_api(
+        &client,
+        &args.ade_url,
+        &[
+            ("sessionId", session_id),
+            ("function", "getResources".to_string()),
+            ("tree", "true".to_string()),
+            ("category", args.category.clone()),
+            ("detail", "2".to_string()),
+        ],
+    )
+    .await?;
+
+    parse_resource_tree(&resources, args.branch.as_deref())
+}
+
+// the number at the end of the short name, with and without its prefix ("TD06" => "6", "td6"),
+// unless another room would get the same alias
+fn default_aliases(short_names: &[String]) -> Vec<Vec<String>> {
+    let candidates: Vec<Vec<String>> = short_names
+        .iter()
+        .map(|short_name| {
+            let prefix = short_name.trim_end_matches(|c: char| c.is_ascii_digit());
+            let Ok(number) = short_name[prefix.len()..].parse::<u32>() else {
+                return Vec::new();
+            };
+
+            [
+                number.to_string(),
+                format!("{prefix}{number}").to_lowercase(),
+            ]
+            .into_iter()
+            .filter(|x| *x != short_name.to_lowercase())
+            .collect()
+        })
+        .collect();
+
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for name in short_names
+        .iter()
+        .map(|x| x.to_lowercase())
+        .chain(candidates.iter().flatten().cloned())
+    {
+        *names.entry(name).or_default() += 1;
+    }
+
+    candidates
+        .into_iter()
+        .map(|x| x.into_iter().filter(|alias| names[alias] == 1).collect())
+        .collect()
+}
+
+/// Builds a room catalogue with a single site from the discovered rooms
+pub fn rooms_to_catalogue(
+    args: &DiscoverArgs,
+    rooms: &[DiscoveredRoom],
+) -> Result<String, Box<dyn Error>> {
+    let mut rooms = rooms.to_vec();
+    rooms.sort_by_key(|x| x.short_name());
+
+    // rooms sharing a short name can't be told apart, their full name is used instead
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for room in &rooms {
+        *counts.entry(room.short_name().to_lowercase()).or_default() += 1;
+    }
+    let short_names: Vec<String> = rooms
+        .iter()
+        .map(|x| match counts[&x.short_name().to_lowercase()] {
+            1 => x.short_name(),
+            _ => x.name.clone(),
+        })
+        .collect();
+
+    let catalogue = Catalogue {
+        sites: vec![CatalogueSite {
+            name: args.site.clone(),
+            ade_url: args.ade_url.clone(),
+            project_id: args.project_id,
+            display_config_id: args.display_config_id,
+            rooms: rooms
+                .iter()
+                .zip(short_names.iter())
+                .zip(default_aliases(&short_names))
+                .map(|((room, short_name), aliases)| CatalogueRoom {
+                    short_name: short_name.clone(),
+                    name: room.name.clone(),
+                    id: room.id,
+                    aliases,
+                    wing: room.building.clone(),
+                })
+                .collect(),
+        }],
+    };
+
+    let data = format!(
+        "# Room catalogue discovered from ADE, check it before replacing rooms.toml with it\n\n{}",
+        toml::to_string(&catalogue)?
+    );
+
+    // the file has to be loadable as it is
+    RoomRegistry::from_toml(&data)?;
+
+    Ok(data)
+}
+
+pub async fn discover(args: DiscoverArgs) -> Result<(), Box<dyn Error>> {
+    let rooms = discover_rooms(&args).await?;
+    if rooms.is_empty() {
+        return Err("no room found in ADE's resource tree")?;
+    }
+
+    fs::write(&args.out, rooms_to_catalogue(&args, &rooms)?)
+        .map_err(|e| format!("couldn't write {}: {e}", args.out.display()))?;
+
+    tracing::info!(
+        "Wrote the {} discovered rooms to {}",
+        rooms.len(),
+        args.out.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, routing::get, Router};
+    use std::path::PathBuf;
+
+    const RESOURCES: &str = include_str!("../../tests/fixtures/ade_resources.xml");
+
+    // answers like ADE's web API, from the recorded resource tree
+    async fn fixture_server() -> String {
+        let app = Router::new().route(
+            "/jsp/webapi",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                let function = params.get("function").map(|x| x.as_str());
+                let session = params.get("sessionId").map(|x| x.as_str());
+                match (function, session) {
+                    (Some("connect"), _) if params.get("login").map(|x| x.as_str()) == Some("ade") => {
+                        r#"<?xml version="1.0" encoding="UTF-8"?><session id="1f2e3d"/>"#.to_string()
+                    }
+                    (Some("setProject"), Some("1f2e3d")) => {
+                        r#"<?xml version="1.0" encoding="UTF-8"?><setProject sessionId="1f2e3d" projectId="1"/>"#.to_string()
+                    }
+                    (Some("getResources"), Some("1f2e3d")) => RESOURCES.to_string(),
+                    _ => r#"<?xml version="1.0" encoding="UTF-8"?><error code="1" name="Bad request"/>"#.to_string(),
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("http://{addr}")
+    }
+
+    fn discover_args(ade_url: String, login: &str) -> DiscoverArgs {
+        DiscoverArgs {
+            ade_url,
+            project_id: 1,
+            display_config_id: 71,
+            site: "enseirb".to_string(),
+            login: login.to_string(),
+            password: "secret".to_string(),
+            category: "classroom".to_string(),
+            branch: Some("ENSEIRB-MATMECA".to_string()),
+            out: PathBuf::from("rooms.discovered.toml"),
+        }
+    }
+
+    #[tokio::test]
+    async fn discovers_the_rooms_of_the_branch() {
+        let args = discover_args(fixture_server().await, "ade");
+        let rooms = discover_rooms(&args).await.unwrap();
+
+        // the amphitheatre of the other school is left out, and TD06 is listed twice
+        assert_eq!(rooms.len(), 5);
+        let td06 = rooms.iter().find(|x| x.short_name() == "TD06").unwrap();
+        assert_eq!(td06.id, 3258);
+        assert_eq!(td06.building.as_deref(), Some("EA"));
+
+        let catalogue = rooms_to_catalogue(&args, &rooms).unwrap();
+        let registry = RoomRegistry::from_toml(&catalogue).unwrap();
+        let site = registry.default_site();
+        assert_eq!(site.from_string("6".to_string()).unwrap().id(), 3258);
+        assert_eq!(site.from_string("td16".to_string()).unwrap().id(), 3290);
+        assert_eq!(
+            site.from_string("TD16".to_string())
+                .unwrap()
+                .wing()
+                .as_deref(),
+            Some("EA")
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_ade_errors() {
+        let args = discover_args(fixture_server().await, "someone");
+        let error = discover_rooms(&args).await.unwrap_err();
+
+        assert!(error.to_string().contains("Bad request"));
+    }
+}
@@ -0,0 +1,195 @@
+use futures::future::BoxFuture;
+use reqwest::Url;
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    caching::cal_caching::CalendarCache,
+    calendar_parsing::rooms::{Room, Site},
+    networking::ade_api_handling::TimeWindow,
+};
+
+/// Where the raw calendars of the rooms come from
+///
+/// ```
+/// use ade::{CalendarSource, Room, Site, TimeWindow};
+/// use futures::future::BoxFuture;
+///
+/// // every room is free all the time
+/// struct EmptySource;
+///
+/// impl CalendarSource for EmptySource {
+///     fn fetch<'a>(
+///         &'a self,
+///         _site: &'a Site,
+///         _room: &'a Room,
+///         _window: TimeWindow,
+///     ) -> BoxFuture<'a, Result<String, String>> {
+///         Box::pin(async {
+///             Ok("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n".to_string())
+///         })
+///     }
+/// }
+/// ```
+pub trait CalendarSource: Send + Sync {
+    /// The calendar (iCalendar data) of the room over the window, or why it couldn't be fetched
+    fn fetch<'a>(
+        &'a self,
+        site: &'a Site,
+        room: &'a Room,
+        window: TimeWindow,
+    ) -> BoxFuture<'a, Result<String, String>>;
+}
+
+/// Where the room calendars come from, along with the cache they go through
+pub struct CalendarStore {
+    pub source: Box<dyn CalendarSource>,
+    pub cache: CalendarCache,
+}
+
+impl CalendarStore {
+    pub fn new(source: impl CalendarSource + 'static, cache: CalendarCache) -> Self {
+        Self {
+            source: Box::new(source),
+            cache,
+        }
+    }
+}
+
+/// Downloads the calendars from the ADE instance of the site
+#[derive(Default)]
+pub struct AdeSource {
+    client: reqwest::Client,
+}
+
+impl AdeSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CalendarSource for AdeSource {
+    // ADE sometimes answers with an error page instead of a calendar
+    fn fetch<'a>(
+        &'a self,
+        site: &'a Site,
+        room: &'a Room,
+        window: TimeWindow,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let url = site.url(room, window.first_date(), window.last_date());
+            let url = Url::parse(&url).map_err(|e| e.to_string())?;
+            let ical = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .and_then(|x| x.error_for_status())
+                .map_err(|e| e.to_string())?
+                .text()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if !ical.trim_start().starts_with("BEGIN:VCALENDAR") {
+                return Err("the answer isn't a calendar".to_string());
+            }
+
+            Ok(ical)
+        })
+    }
+}
+
+/// Reads the calendars from `.ics` files named after the short name of the rooms, in a directory
+/// per site (`<dir>/<site>/TD06.ics`) or directly in the directory (`<dir>/TD06.ics`).
+/// The files can cover any time range, only the events of the window are used.
+pub struct DirectorySource {
+    dir: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl CalendarSource for DirectorySource {
+    fn fetch<'a>(
+        &'a self,
+        site: &'a Site,
+        room: &'a Room,
+        _window: TimeWindow,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let file_name = format!("{}.ics", room.short_name());
+
+            for path in [
+                self.dir.join(site.name()).join(&file_name),
+                self.dir.join(&file_name),
+            ] {
+                if let Ok(data) = tokio::fs::read_to_string(&path).await {
+                    return Ok(data);
+                }
+            }
+
+            Err(format!("no {file_name} in {}", self.dir.display()))
+        })
+    }
+}
+
+/// Serves calendars given in advance, by room id, to run the whole pipeline without ADE
+/// (see the example of the crate). The other rooms are missing.
+#[derive(Default)]
+pub struct FixtureSource {
+    calendars: HashMap<u32, String>,
+}
+
+impl FixtureSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, room: &Room, calendar: &str) -> Self {
+        self.calendars.insert(room.id(), calendar.to_string());
+        self
+    }
+}
+
+impl CalendarSource for FixtureSource {
+    fn fetch<'a>(
+        &'a self,
+        _site: &'a Site,
+        room: &'a Room,
+        _window: TimeWindow,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            self.calendars
+                .get(&room.id())
+                .cloned()
+                .ok_or_else(|| format!("no calendar for {}", room.short_name()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar_parsing::rooms::RoomRegistry;
+    use chrono::NaiveDate;
+
+    #[tokio::test]
+    async fn directory_source_reads_the_files_named_after_the_rooms() {
+        let registry = RoomRegistry::from_toml(include_str!("../../rooms.toml")).unwrap();
+        let site = registry.default_site();
+        let source = DirectorySource::new(PathBuf::from("tests/fixtures"));
+        let window =
+            TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 21).unwrap(), 1).unwrap();
+
+        let td06 = site.from_string("TD06".to_string()).unwrap();
+        assert_eq!(
+            source.fetch(&site, &td06, window).await.unwrap(),
+            include_str!("../../tests/fixtures/TD06.ics")
+        );
+
+        let td07 = site.from_string("TD07".to_string()).unwrap();
+        assert!(source.fetch(&site, &td07, window).await.is_err());
+    }
+}
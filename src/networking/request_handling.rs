@@ -2,27 +2,70 @@ use std::{collections::HashMap, net::SocketAddr};
 
 use std::sync::Arc;
 
-use crate::calendar_parsing::rooms::EnseirbRoom;
-use crate::cli_params::arg_parsing::Args;
-use clap::Parser;
-use itertools::Itertools;
+use crate::calendar_parsing::intervals::Interval;
+use crate::calendar_parsing::rooms::{Room, RoomFilter, RoomRegistry, Site};
+use crate::free_rooms::{AppData, ServerConfig};
+use crate::networking::ade_api_handling::TimeWindow;
+use crate::networking::calendar_source::CalendarStore;
+use chrono::{prelude::*, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
 
-use crate::calendar_parsing::parsing::{get_free_rooms_calendar, get_zik_calendar};
+use crate::calendar_parsing::parsing::{
+    compute_free_rooms_slots, find_free_rooms, get_free_rooms_calendar, get_free_rooms_slots,
+    get_rooms_free_at, get_zik_calendar, get_zik_slots,
+};
+use crate::calendar_parsing::schedules::get_room_schedules;
+use crate::calendar_parsing::slots::{DataNotice, FoundRooms, FreeRoomsNow, FreeRoomsSlots};
+use crate::calendar_parsing::zik_policy::ZikPolicy;
+use crate::html::{escape, free_rooms_page, zik_page, ZikView};
 
-use axum::{body::Body, extract::Query, response::Response, routing::get, Router};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+    routing::get,
+    Router,
+};
+use itertools::Itertools;
+use reqwest::Url;
 
 enum Mode {
     FreeRooms,
     Zik,
+    Find,
+}
+
+// the modes answered with a calendar, JSON slots or a timetable. The find mode is answered on its own
+enum CalendarMode {
+    FreeRooms,
+    Zik,
+}
+
+enum Format {
+    Ics,
+    Json,
+    Html,
 }
 
-pub async fn serve() {
-    let args = Args::parse();
-    let port = args.port;
+#[derive(Clone)]
+struct AppState {
+    store: Arc<CalendarStore>,
+    registry: Arc<RoomRegistry>,
+    zik_policy: Arc<ZikPolicy>,
+    config: ServerConfig,
+}
 
+/// Serves the API on every interface, until the listener fails
+pub async fn serve(data: AppData, config: ServerConfig, port: u16) {
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
-    let app = Router::new().route("/", get(handle_connection));
+    let app = router(AppState {
+        store: data.store,
+        registry: data.registry,
+        zik_policy: data.zik_policy,
+        config,
+    });
 
     let listener = match tokio::net::TcpListener::bind(addr.to_string()).await {
         Ok(_listener) => _listener,
@@ -37,80 +80,893 @@ pub async fn serve() {
     let _ = axum::serve(listener, app).await; // TODO: LOGGING
 }
 
-fn parse_rooms(rooms: String) -> Arc<Vec<EnseirbRoom>> {
-    // format: rooms separated by a ,
-    let roomlist: Vec<EnseirbRoom> = rooms
-        .split(',')
-        .filter_map(|x| EnseirbRoom::from_string(x.to_string()))
-        .dedup()
-        .collect();
-    Arc::from(roomlist)
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(handle_connection))
+        .route("/now", get(handle_now))
+        .with_state(state)
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain;charset=UTF-8")
+        .body(Body::from(message))
+        .unwrap()
+}
+
+fn parse_date(val: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(val, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date: {val} (expected YYYY-MM-DD)"))
+}
+
+// the windows can only start around today, so that requests can't fill the cache with the
+// calendars of dates nobody will ask for again
+fn check_first_date(
+    first_date: NaiveDate,
+    today: NaiveDate,
+    config: &ServerConfig,
+) -> Result<(), String> {
+    let earliest = today - Duration::days(i64::from(config.max_days_back));
+    let latest = today + Duration::days(i64::from(config.max_days_ahead));
+
+    if first_date < earliest || first_date > latest {
+        return Err(format!(
+            "Invalid date: {first_date} (the dates have to be between {earliest} and {latest})"
+        ));
+    }
+
+    Ok(())
+}
+
+// format: start=YYYY-MM-DD, end=YYYY-MM-DD (both included) or days=N
+// the window starts today by default, and can never be longer than the host's max-days
+fn parse_time_window(
+    params: &HashMap<String, String>,
+    default_days: u16,
+    config: &ServerConfig,
+    today: NaiveDate,
+) -> Result<TimeWindow, String> {
+    let first_date = match params.get("start") {
+        Some(thing) => parse_date(thing)?,
+        None => today,
+    };
+    check_first_date(first_date, today, config)?;
+
+    let window = match (params.get("end"), params.get("days")) {
+        (Some(thing), _) => TimeWindow::new(first_date, parse_date(thing)?)
+            .ok_or("The end date can't be before the start date".to_string())?,
+        (None, Some(thing)) => {
+            let days: u16 = thing
+                .parse()
+                .map_err(|_| format!("Invalid number of days: {thing}"))?;
+            TimeWindow::from_days(first_date, days.min(config.max_days))
+                .ok_or(format!("Invalid number of days: {thing}"))?
+        }
+        (None, None) => TimeWindow::from_days(first_date, default_days)
+            .ok_or("Invalid date: the window ends too late".to_string())?,
+    };
+
+    Ok(window.clamp(config.max_days))
+}
+
+// format: HH:MM (today), YYYY-MM-DDTHH:MM (in the timezone of the site) or RFC 3339
+fn parse_instant(val: &str, timezone: Tz) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(val) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    let naive = match NaiveTime::parse_from_str(val, "%H:%M") {
+        Ok(time) => Utc::now()
+            .with_timezone(&timezone)
+            .date_naive()
+            .and_time(time),
+        Err(_) => NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M")
+            .map_err(|_| format!("Invalid time: {val} (expected HH:MM or YYYY-MM-DDTHH:MM)"))?,
+    };
+
+    match timezone.from_local_datetime(&naive).earliest() {
+        Some(date) => Ok(date.with_timezone(&Utc)),
+        None => Err(format!("Invalid time: {val} doesn't exist in {timezone}")),
+    }
 }
 
-async fn handle_connection(Query(params): Query<HashMap<String, String>>) -> Response<Body> {
+// format: 2h, 90m, 1h30 or 1h30m (a plain number is a number of minutes)
+fn parse_duration(val: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration: {val} (expected something like 2h, 90m or 1h30)");
+
+    let (hours, minutes) = match val.split_once('h') {
+        Some((hours, minutes)) => (hours, minutes.trim_end_matches('m')),
+        None => ("0", val.trim_end_matches('m')),
+    };
+
+    let hours: i64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i64 = match minutes {
+        "" => 0,
+        _ => minutes.parse().map_err(|_| invalid())?,
+    };
+
+    // out of chrono's range for huge numbers
+    let duration = Duration::try_hours(hours)
+        .zip(Duration::try_minutes(minutes))
+        .and_then(|(hours, minutes)| hours.checked_add(&minutes))
+        .ok_or_else(invalid)?;
+    if duration <= Duration::zero() {
+        return Err(invalid());
+    }
+
+    Ok(duration)
+}
+
+fn parse_site(
+    registry: &RoomRegistry,
+    params: &HashMap<String, String>,
+) -> Result<Arc<Site>, String> {
+    registry.site_or_default(params.get("site").map(|x| x.as_str()))
+}
+
+// the unknown rooms are left out
+fn parse_rooms(site: &Site, rooms: &str) -> Arc<Vec<Room>> {
+    let (rooms, unknown) = site.select_rooms(rooms);
+    if !unknown.is_empty() {
+        tracing::info!("Unknown rooms requested: {}", unknown.join(", "));
+    }
+
+    rooms
+}
+
+// min-capacity=<places> and has=<feature>,<feature>... (features some room of the site has)
+fn parse_room_filter(site: &Site, params: &HashMap<String, String>) -> Result<RoomFilter, String> {
+    let mut filter = RoomFilter::default();
+
+    if let Some(thing) = params.get("min-capacity") {
+        filter.min_capacity =
+            Some(thing.parse().map_err(|_| {
+                format!("Invalid min-capacity {thing}, expected a number of places")
+            })?);
+    }
+
+    if let Some(thing) = params.get("has") {
+        filter.features = thing
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect();
+        filter
+            .check(site)
+            .map_err(|e| format!("Invalid has parameter: {e}"))?;
+    }
+
+    Ok(filter)
+}
+
+// browsers ask for HTML first, calendar apps don't
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| {
+            x.split(',')
+                .any(|media_type| media_type.trim().starts_with("text/html"))
+        })
+}
+
+// the links to the day and week around the window, and to the windows before and after it.
+// the other parameters of the request are kept
+fn html_navigation(params: &HashMap<String, String>, window: TimeWindow) -> String {
+    let first_day = window.first_day();
+    let monday = first_day - Duration::days(i64::from(first_day.weekday().num_days_from_monday()));
+    let days = window.days();
+
+    let link = |label: &str, start: NaiveDate, days: i64| {
+        let mut url = Url::parse("http://localhost/").unwrap();
+        url.query_pairs_mut()
+            .extend_pairs(
+                params
+                    .iter()
+                    .filter(|(key, _)| !["start", "end", "days"].contains(&key.as_str()))
+                    .sorted(),
+            )
+            .append_pair("start", &start.format("%Y-%m-%d").to_string())
+            .append_pair("days", &days.to_string());
+
+        format!(
+            "<a href=\"?{}\">{}</a>",
+            escape(url.query().unwrap_or_default()),
+            escape(label)
+        )
+    };
+
+    format!(
+        "<nav>{}{}{}{}</nav>",
+        link("← Précédent", first_day - Duration::days(days), days),
+        link("Jour", first_day, 1),
+        link("Semaine", monday, 7),
+        link("Suivant →", first_day + Duration::days(days), days),
+    )
+}
+
+// the same slots as the calendars, as a timetable for browsers
+async fn html_response(
+    state: &AppState,
+    params: &HashMap<String, String>,
+    mode: CalendarMode,
+    site: Arc<Site>,
+    roomlist: Arc<Vec<Room>>,
+    window: TimeWindow,
+) -> Response<Body> {
+    let navigation = html_navigation(params, window);
+    let policy = &state.zik_policy;
+
+    let content = match mode {
+        CalendarMode::Zik => {
+            tracing::info!["chosen mode: zik (html)"];
+            let (slots, notice) = get_zik_slots(state.store.clone(), policy, window).await;
+            let zik = ZikView {
+                slots: &slots,
+                default: policy.default_level(),
+                notice: &notice,
+            };
+
+            zik_page("Zik", &navigation, window, policy.site().timezone(), &zik)
+        }
+        CalendarMode::FreeRooms => {
+            tracing::info!["chosen mode: free rooms (html)"];
+            let schedules =
+                get_room_schedules(state.store.clone(), site.clone(), roomlist, window).await;
+            let slots = compute_free_rooms_slots(&schedules);
+            let notice = DataNotice::from_schedules(&schedules);
+            let title = format!("Salles libres ({})", site.name());
+            let timezone = site.timezone();
+
+            // the level of the Zik is shown next to the rooms of its site, and comes from the
+            // cache like the Zik calendar
+            if policy.site().name() == site.name() {
+                let (zik_slots, zik_notice) =
+                    get_zik_slots(state.store.clone(), policy, window).await;
+                let zik = ZikView {
+                    slots: &zik_slots,
+                    default: policy.default_level(),
+                    notice: &zik_notice,
+                };
+
+                free_rooms_page(
+                    &title,
+                    &navigation,
+                    window,
+                    timezone,
+                    &slots,
+                    &notice,
+                    Some(&zik),
+                )
+            } else {
+                free_rooms_page(&title, &navigation, window, timezone, &slots, &notice, None)
+            }
+        }
+    };
+
+    Response::builder()
+        .header("Content-Type", "text/html;charset=UTF-8")
+        .header("Vary", "Accept")
+        .body(Body::from(content))
+        .unwrap()
+}
+
+async fn handle_connection(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response<Body> {
     tracing::info!("Got a connection!");
     let mut mode: Mode = Mode::FreeRooms;
+    // without a format parameter, browsers get the HTML timetable
+    let mut format: Format = match accepts_html(&headers) {
+        true => Format::Html,
+        false => Format::Ics,
+    };
+
+    let site: Arc<Site> = match parse_site(&state.registry, &params) {
+        Ok(_site) => _site,
+        Err(e) => {
+            tracing::info!("Invalid site requested: {e}");
+            return error_response(StatusCode::NOT_FOUND, e);
+        }
+    };
 
-    let mut roomlist: Arc<Vec<EnseirbRoom>> = Arc::new(vec![
-        EnseirbRoom::TD01,
-        EnseirbRoom::TD02,
-        EnseirbRoom::TD03,
-        EnseirbRoom::TD04,
-        EnseirbRoom::TD05,
-        EnseirbRoom::TD06,
-        EnseirbRoom::TD07,
-        EnseirbRoom::TD08,
-        EnseirbRoom::TD09,
-        EnseirbRoom::TD10,
-        EnseirbRoom::TD11,
-        EnseirbRoom::TD12,
-        EnseirbRoom::TD13,
-        EnseirbRoom::TD14,
-        EnseirbRoom::TD15,
-        EnseirbRoom::TD17,
-        EnseirbRoom::TD20,
-        EnseirbRoom::TD21,
-        EnseirbRoom::TD22,
-        EnseirbRoom::TD23,
-        EnseirbRoom::TD24,
-        EnseirbRoom::TD25,
-        EnseirbRoom::TD26,
-        EnseirbRoom::TD27,
-        EnseirbRoom::TD28,
-    ]);
+    let mut roomlist: Arc<Vec<Room>> = site.rooms();
 
     if let Some(thing) = params.get("mode") {
         match thing.as_str() {
             "zik" => mode = Mode::Zik,
+            "find" => mode = Mode::Find,
             _ => mode = Mode::FreeRooms,
         }
     }
 
+    if let Some(thing) = params.get("format") {
+        match thing.as_str() {
+            "json" => format = Format::Json,
+            "html" => format = Format::Html,
+            _ => format = Format::Ics,
+        }
+    }
+
     if let Some(thing) = params.get("room-list") {
-        roomlist = parse_rooms(thing.into());
+        roomlist = parse_rooms(&site, thing);
+    }
+
+    // the rooms that don't meet the requirements are never fetched
+    roomlist = match parse_room_filter(&site, &params) {
+        Ok(filter) => filter.apply(&roomlist),
+        Err(e) => {
+            tracing::info!("Invalid room filter requested: {e}");
+            return error_response(StatusCode::BAD_REQUEST, e);
+        }
+    };
+
+    if let Mode::Zik = mode {
+        if state.zik_policy.site().name() != site.name() {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                format!("There is no Zik at site {}", site.name()),
+            );
+        }
+    }
+
+    let mode = match mode {
+        Mode::FreeRooms => CalendarMode::FreeRooms,
+        Mode::Zik => CalendarMode::Zik,
+        Mode::Find => {
+            tracing::info!["chosen mode: find"];
+            return handle_find(&state, &params, site, roomlist).await;
+        }
+    };
+
+    let default_days = match mode {
+        CalendarMode::Zik => state.config.zik_timespan.saturating_mul(7),
+        CalendarMode::FreeRooms => state.config.free_rooms_timespan,
+    };
+    let window = match parse_time_window(&params, default_days, &state.config, site.today()) {
+        Ok(_window) => _window,
+        Err(e) => {
+            tracing::info!("Invalid time window requested: {e}");
+            return error_response(StatusCode::BAD_REQUEST, e);
+        }
+    };
+
+    if let Format::Html = format {
+        return html_response(&state, &params, mode, site, roomlist, window).await;
     }
 
-    let content: String;
+    if let Format::Json = format {
+        let CalendarMode::FreeRooms = mode else {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "The JSON format is only available for the free rooms mode".to_string(),
+            );
+        };
 
-    match mode {
-        Mode::Zik => {
+        tracing::info!["chosen mode: free rooms (json)"];
+        let (slots, notice) =
+            get_free_rooms_slots(state.store.clone(), site, roomlist, window).await;
+
+        return match serde_json::to_string(&FreeRoomsSlots { slots, notice }) {
+            Ok(content) => Response::builder()
+                .header("Content-Type", "application/json;charset=UTF-8")
+                .body(Body::from(content))
+                .unwrap(),
+            Err(e) => {
+                tracing::error!("Failed to serialize the free rooms: {e}");
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }
+        };
+    }
+
+    let content: String = match mode {
+        CalendarMode::Zik => {
             tracing::info!["chosen mode: zik"];
-            content = format!("{}", get_zik_calendar().await)
+            format!(
+                "{}",
+                get_zik_calendar(state.store.clone(), state.zik_policy.clone(), window).await
+            )
         }
-        Mode::FreeRooms => {
+        CalendarMode::FreeRooms => {
             tracing::info!["chosen mode: free rooms"];
-            content = format!["{}", get_free_rooms_calendar(roomlist).await];
+            format![
+                "{}",
+                get_free_rooms_calendar(state.store.clone(), site, roomlist, window).await
+            ]
         }
-    }
+    };
 
     Response::builder()
         .header("Content-Type", "text/calendar;charset=UTF-8")
         .header("Content-Disposition", "inline; filename=ADECal.ics")
+        .header("Vary", "Accept")
         .body(Body::from(content))
         .unwrap() // TODO: error handling
 }
 
+async fn handle_find(
+    state: &AppState,
+    params: &HashMap<String, String>,
+    site: Arc<Site>,
+    roomlist: Arc<Vec<Room>>,
+) -> Response<Body> {
+    let timezone = site.timezone();
+    let mut start: DateTime<Utc> = Utc::now();
+
+    if let Some(thing) = params.get("at") {
+        start = match parse_instant(thing, timezone) {
+            Ok(_start) => _start,
+            Err(e) => {
+                tracing::info!("Invalid time requested: {e}");
+                return error_response(StatusCode::BAD_REQUEST, e);
+            }
+        };
+    }
+
+    let duration = match params.get("duration").map(|x| parse_duration(x)) {
+        Some(Ok(_duration)) => _duration,
+        Some(Err(e)) => {
+            tracing::info!("Invalid duration requested: {e}");
+            return error_response(StatusCode::BAD_REQUEST, e);
+        }
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "The find mode needs a duration".to_string(),
+            )
+        }
+    };
+    // the window has to cover the whole interval, but can't be longer than the host's max-days
+    let max_duration = Duration::days(i64::from(state.config.max_days));
+    let end = match start.checked_add_signed(duration) {
+        Some(_end) if duration <= max_duration => _end,
+        _ => {
+            tracing::info!("Invalid duration requested: {duration}");
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Invalid duration: the interval can't be longer than {} days",
+                    state.config.max_days
+                ),
+            );
+        }
+    };
+
+    let first_date = start.with_timezone(&timezone).date_naive();
+    if let Err(e) = check_first_date(first_date, site.today(), &state.config) {
+        tracing::info!("Invalid time requested: {e}");
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+    let last_date = end.with_timezone(&timezone).date_naive();
+    let needed_days = (last_date - first_date).num_days() + 1;
+    if needed_days > i64::from(state.config.max_days) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "The interval can't cover more than {} days",
+                state.config.max_days
+            ),
+        );
+    }
+    let days = state
+        .config
+        .free_rooms_timespan
+        .max(needed_days as u16)
+        .min(state.config.max_days);
+    // the first date is checked, but max-days-ahead may reach the last date chrono can represent
+    let Some(window) = TimeWindow::from_days(first_date, days) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid date: the window ends too late".to_string(),
+        );
+    };
+
+    let (rooms, notice) = find_free_rooms(
+        state.store.clone(),
+        site,
+        roomlist,
+        window,
+        Interval { start, end },
+    )
+    .await;
+    let found_rooms = FoundRooms {
+        start,
+        end,
+        rooms,
+        notice,
+    };
+
+    match serde_json::to_string(&found_rooms) {
+        Ok(content) => Response::builder()
+            .header("Content-Type", "application/json;charset=UTF-8")
+            .body(Body::from(content))
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Failed to serialize the found rooms: {e}");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+async fn handle_now(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response<Body> {
+    tracing::info!("Got a connection on /now!");
+
+    let site: Arc<Site> = match parse_site(&state.registry, &params) {
+        Ok(_site) => _site,
+        Err(e) => {
+            tracing::info!("Invalid site requested: {e}");
+            return error_response(StatusCode::NOT_FOUND, e);
+        }
+    };
+
+    let mut roomlist: Arc<Vec<Room>> = site.rooms();
+
+    if let Some(thing) = params.get("room-list") {
+        roomlist = parse_rooms(&site, thing);
+    }
+
+    // the rooms that don't meet the requirements are never fetched
+    roomlist = match parse_room_filter(&site, &params) {
+        Ok(filter) => filter.apply(&roomlist),
+        Err(e) => {
+            tracing::info!("Invalid room filter requested: {e}");
+            return error_response(StatusCode::BAD_REQUEST, e);
+        }
+    };
+
+    let mut at: DateTime<Utc> = Utc::now();
+
+    if let Some(thing) = params.get("at") {
+        at = match parse_instant(thing, site.timezone()) {
+            Ok(_at) => _at,
+            Err(e) => {
+                tracing::info!("Invalid time requested: {e}");
+                return error_response(StatusCode::BAD_REQUEST, e);
+            }
+        };
+    }
+
+    // distances in the building model, to list the nearest rooms first
+    let mut distances: Option<HashMap<String, u32>> = None;
+
+    if let Some(thing) = params.get("near") {
+        distances = match site.distances_from(thing) {
+            Some(_distances) => Some(_distances),
+            None => {
+                tracing::info!("Invalid room requested: {thing}");
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    format!("Unknown room or place {thing}"),
+                );
+            }
+        };
+    }
+
+    let first_date = at.with_timezone(&site.timezone()).date_naive();
+    if let Err(e) = check_first_date(first_date, site.today(), &state.config) {
+        tracing::info!("Invalid time requested: {e}");
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+
+    // same window as the free rooms mode, so that both share the cached calendars
+    let Some(window) = TimeWindow::from_days(first_date, state.config.free_rooms_timespan) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid date: the window ends too late".to_string(),
+        );
+    };
+
+    let (rooms, notice) = get_rooms_free_at(state.store.clone(), site, roomlist, window, at).await;
+    let mut free_rooms = FreeRoomsNow {
+        at,
+        free_rooms: rooms,
+        notice,
+    };
+
+    if let Some(distances) = distances {
+        for x in free_rooms.free_rooms.iter_mut() {
+            x.distance = distances.get(&x.room.short_name).copied();
+        }
+        // the rooms that aren't connected come last, and the sort keeps the longest free first otherwise
+        free_rooms
+            .free_rooms
+            .sort_by_key(|x| x.distance.unwrap_or(u32::MAX));
+    }
+
+    match serde_json::to_string(&free_rooms) {
+        Ok(content) => Response::builder()
+            .header("Content-Type", "application/json;charset=UTF-8")
+            .body(Body::from(content))
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Failed to serialize the free rooms: {e}");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::{
+        caching::cal_caching::{CacheConfig, CalendarCache},
+        networking::calendar_source::DirectorySource,
+    };
+    use std::path::PathBuf;
+
+    // serves the fixture calendars: TD06 is busy from 08:00 to 10:00 (UTC) on 2024-10-21,
+    // TD15 from 09:00 to 12:00, and the other rooms are missing. The fixtures are in the past,
+    // so requests can go back 100 years
+    async fn fixture_server() -> String {
+        let registry = RoomRegistry::from_toml(include_str!("../../rooms.toml")).unwrap();
+        let zik_policy = ZikPolicy::from_toml(include_str!("../../zik.toml"), &registry).unwrap();
+        let app = router(AppState {
+            store: Arc::new(CalendarStore::new(
+                DirectorySource::new(PathBuf::from("tests/fixtures")),
+                CalendarCache::in_memory(CacheConfig::default()),
+            )),
+            registry: Arc::new(registry),
+            zik_policy: Arc::new(zik_policy),
+            config: ServerConfig {
+                max_days_back: 36500,
+                ..ServerConfig::default()
+            },
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("http://{addr}")
+    }
+
+    async fn get(url: String, accept: Option<&str>) -> (String, String) {
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = request.send().await.unwrap();
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        (content_type, response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn browsers_get_the_html_timetable() {
+        let server = fixture_server().await;
+        let query = "room-list=6,7,8,15&start=2024-10-21";
+        let browser = Some("text/html,application/xhtml+xml,*/*;q=0.8");
+
+        let (content_type, page) = get(format!("{server}/?{query}"), browser).await;
+        assert!(content_type.starts_with("text/html"));
+        assert!(page.contains("<h2>lundi 21/10</h2>"));
+        assert!(page.contains("<th>Salles libres</th><th>Zik</th>"));
+        assert!(page.contains("<span title=\"EA-S225 (TD15)\">TD15</span>"));
+        assert!(page.contains("Données indisponibles : TD07, TD08"));
+        assert!(!page.contains("<script"));
+
+        // calendar apps still get the calendar, and the format parameter wins over the header
+        let (content_type, _) = get(format!("{server}/?{query}"), Some("*/*")).await;
+        assert!(content_type.starts_with("text/calendar"));
+        let (content_type, _) = get(format!("{server}/?{query}&format=ics"), browser).await;
+        assert!(content_type.starts_with("text/calendar"));
+        let (content_type, _) = get(format!("{server}/?{query}&format=html"), None).await;
+        assert!(content_type.starts_with("text/html"));
+
+        let (_, page) = get(format!("{server}/?mode=zik&{query}&format=html"), None).await;
+        assert!(page.contains("<th>Niveau</th><th>Examens</th>"));
+        assert!(page.contains("Faible volume, fenêtre fermée."));
+    }
+
+    #[test]
+    fn the_default_config_is_the_one_of_the_command_line() {
+        use crate::cli_params::arg_parsing::Args;
+        use clap::Parser;
+
+        let args = Args::parse_from(["ade"]);
+        assert_eq!(
+            ServerConfig::default(),
+            ServerConfig {
+                free_rooms_timespan: args.free_rooms_timespan,
+                zik_timespan: args.zik_timespan,
+                max_days: args.max_days,
+                max_days_back: args.max_days_back,
+                max_days_ahead: args.max_days_ahead,
+            }
+        );
+    }
+
+    #[test]
+    fn instants_are_read_in_the_timezone_of_the_site() {
+        let paris = chrono_tz::Europe::Paris;
+
+        // summer time, then winter time, whatever the timezone of the host
+        assert_eq!(
+            parse_instant("2024-10-21T10:00", paris).unwrap(),
+            Utc.with_ymd_and_hms(2024, 10, 21, 8, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_instant("2024-11-04T10:00", paris).unwrap(),
+            Utc.with_ymd_and_hms(2024, 11, 4, 9, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_instant("14:00", paris)
+                .unwrap()
+                .with_timezone(&paris)
+                .time(),
+            NaiveTime::from_hms_opt(14, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_instant("2024-10-21T10:00:00+00:00", paris).unwrap(),
+            Utc.with_ymd_and_hms(2024, 10, 21, 10, 0, 0).unwrap()
+        );
+        // the clocks go forward at 02:00
+        assert!(parse_instant("2025-03-30T02:30", paris).is_err());
+    }
+
+    #[test]
+    fn windows_only_start_around_today() {
+        let config = ServerConfig::default();
+        let today = NaiveDate::from_ymd_opt(2024, 10, 21).unwrap();
+        let window = |query: &[(&str, &str)]| {
+            let params = query
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            parse_time_window(&params, 1, &config, today)
+        };
+
+        assert_eq!(window(&[]).unwrap().first_day(), today);
+        assert_eq!(window(&[("start", "2024-10-14")]).unwrap().days(), 1);
+        assert_eq!(
+            window(&[("start", "2025-10-21"), ("days", "7")])
+                .unwrap()
+                .last_date(),
+            "2025-10-27"
+        );
+        assert!(window(&[("start", "2024-10-13")]).is_err());
+        assert!(window(&[("start", "2025-10-22")]).is_err());
+        assert!(window(&[("start", "1000-01-01")]).is_err());
+        assert!(window(&[("start", "9999-12-31"), ("days", "7")]).is_err());
+        // the length is still clamped to max-days
+        assert_eq!(
+            window(&[("end", "9999-12-31")]).unwrap().days(),
+            i64::from(config.max_days)
+        );
+    }
+
+    #[tokio::test]
+    async fn dates_far_from_today_are_rejected() {
+        let server = fixture_server().await;
+
+        let status = |query: &'static str| {
+            let url = format!("{server}/?{query}");
+            async move { reqwest::get(url).await.unwrap().status() }
+        };
+
+        assert_eq!(
+            status("start=9999-12-31&days=7").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status("start=1000-01-01").await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn json_lists_the_missing_rooms_without_slots() {
+        let server = fixture_server().await;
+
+        // none of these rooms is in the fixtures
+        let response = reqwest::get(format!(
+            "{server}/?format=json&room-list=7,8,9&start=2024-10-21"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+
+        assert_eq!(json["slots"], serde_json::json!([]));
+        assert_eq!(json["stale_rooms"], serde_json::json!([]));
+        let missing: Vec<&str> = json["missing_rooms"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["short_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(missing, ["TD07", "TD08", "TD09"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_features_are_rejected() {
+        let server = fixture_server().await;
+
+        let response = reqwest::get(format!("{server}/now?has=projecteur"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let message = response.text().await.unwrap();
+        assert!(message.contains("unknown feature projecteur"));
+        assert!(message.contains("wheelchair-access"));
+
+        let response = reqwest::get(format!(
+            "{server}/?format=json&start=2024-10-21&has=Wheelchair-Access"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn huge_durations_are_rejected() {
+        let server = fixture_server().await;
+
+        for query in [
+            "mode=find&duration=99999999999999h",
+            "mode=find&at=2024-10-21T10:00&duration=3000000000h",
+            "mode=find&at=2024-10-21T10:00&duration=2000000000h",
+            "mode=find&at=2024-10-21T10:00&duration=745h",
+        ] {
+            let response = reqwest::get(format!("{server}/?{query}")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+            assert!(response
+                .text()
+                .await
+                .unwrap()
+                .starts_with("Invalid duration"));
+        }
+
+        let response = reqwest::get(format!(
+            "{server}/?mode=find&at=2024-10-21T10:00&duration=2h"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn find_answers_with_json_whatever_the_format() {
+        let server = fixture_server().await;
+        let query = "mode=find&room-list=6,15&at=2024-10-21T12:00&duration=1h";
+        let browser = Some("text/html,application/xhtml+xml,*/*;q=0.8");
+
+        for (url, accept) in [
+            (format!("{server}/?{query}"), browser),
+            (format!("{server}/?{query}&format=html"), None),
+            (format!("{server}/?{query}&format=ics"), None),
+        ] {
+            let (content_type, body) = get(url, accept).await;
+            assert!(content_type.starts_with("application/json"));
+            assert!(body.contains("\"short_name\":\"TD06\""));
+        }
+    }
+
+    #[test]
+    fn navigation_keeps_the_other_parameters() {
+        let params = HashMap::from([
+            ("room-list".to_string(), "6,7".to_string()),
+            ("format".to_string(), "html".to_string()),
+            ("end".to_string(), "2024-10-25".to_string()),
+        ]);
+        // a Wednesday
+        let window =
+            TimeWindow::from_days(NaiveDate::from_ymd_opt(2024, 10, 23).unwrap(), 1).unwrap();
+
+        let navigation = html_navigation(&params, window);
+        assert!(navigation
+            .contains("<a href=\"?format=html&amp;room-list=6%2C7&amp;start=2024-10-21&amp;days=7\">Semaine</a>"));
+        assert!(navigation.contains("start=2024-10-22&amp;days=1\">← Précédent"));
+        assert!(!navigation.contains("end="));
+    }
+
     /* #[tokio::test]
     async fn request_handling_test_free_rooms() {
         todo!() // TODO: this test
@@ -0,0 +1,399 @@
+use chrono::{prelude::*, Duration};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use std::{error::Error, fs, sync::Arc};
+
+use crate::{
+    calendar_parsing::{
+        intervals::{subtract, Interval},
+        parsing::{
+            compute_free_rooms_slots, compute_zik_levels, get_free_rooms_calendar,
+            get_free_rooms_slots, get_rooms_free_at, get_zik_calendar, get_zik_schedules,
+        },
+        rooms::{Room, Site},
+        schedules::get_room_schedules,
+        slots::{DataNotice, FreeRoomNow, FreeRoomsSlot, FreeRoomsSlots},
+        zik_policy::{ZikLevel, ZikPolicy},
+    },
+    cli_params::arg_parsing::{ExportArgs, ExportFormat, ExportedCalendar, FreeArgs, ZikArgs},
+    free_rooms::AppData,
+    networking::ade_api_handling::TimeWindow,
+};
+
+// format: today, tomorrow or YYYY-MM-DD
+fn parse_day(val: &str, today: NaiveDate) -> Result<NaiveDate, String> {
+    match val.to_lowercase().as_str() {
+        "today" => Ok(today),
+        "tomorrow" => Ok(today + Duration::days(1)),
+        _ => NaiveDate::parse_from_str(val, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid day: {val} (expected today, tomorrow or YYYY-MM-DD)")),
+    }
+}
+
+// format: HH:MM, in the timezone of the site on the given day
+fn parse_time(val: &str, day: NaiveDate, timezone: Tz) -> Result<DateTime<Utc>, String> {
+    let time = NaiveTime::parse_from_str(val, "%H:%M")
+        .map_err(|_| format!("Invalid time: {val} (expected HH:MM)"))?;
+
+    match timezone.from_local_datetime(&day.and_time(time)).earliest() {
+        Some(date) => Ok(date.with_timezone(&Utc)),
+        None => Err(format!("Invalid time: {val} doesn't exist in {timezone}")),
+    }
+}
+
+// the windows of the subcommands start on the given day
+fn window(day: NaiveDate, days: u16) -> Result<TimeWindow, String> {
+    TimeWindow::from_days(day, days).ok_or_else(|| format!("Invalid day: {day} is too far away"))
+}
+
+// all the rooms of the site if there are none.
+// unlike in the URL parameters, an unknown room is an error rather than left out
+fn parse_rooms(site: &Site, rooms: Option<&str>) -> Result<Arc<Vec<Room>>, String> {
+    let Some(rooms) = rooms else {
+        return Ok(site.rooms());
+    };
+
+    match site.select_rooms(rooms) {
+        (rooms, unknown) if unknown.is_empty() => Ok(rooms),
+        (_, unknown) => Err(format!("Unknown room: {}", unknown.join(", "))),
+    }
+}
+
+// the times are shown in the timezone of the site, like in its calendars
+fn site_time(instant: &DateTime<Utc>, timezone: Tz) -> String {
+    instant
+        .with_timezone(&timezone)
+        .format("%d/%m %H:%M")
+        .to_string()
+}
+
+// e.g. "45min", "2h" or "1h30"
+fn format_minutes(minutes: i64) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes}min"),
+        (hours, 0) => format!("{hours}h"),
+        (hours, minutes) => format!("{hours}h{minutes:02}"),
+    }
+}
+
+// the columns are as wide as their widest cell, and the header is underlined
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| {
+            rows.iter()
+                .map(|row| row[index].chars().count())
+                .chain([header.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let line = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    [
+        line(headers.iter().map(|x| x.to_string()).collect()),
+        line(widths.iter().map(|x| "-".repeat(*x)).collect()),
+    ]
+    .into_iter()
+    .chain(rows.iter().map(|row| line(row.clone())))
+    .join("\n")
+}
+
+// the stale and missing rooms, after the table
+fn with_notice(output: String, notice: &DataNotice) -> String {
+    if notice.is_empty() {
+        return output;
+    }
+
+    format!("{output}\n\n{}", notice.description())
+}
+
+fn free_slots_table(slots: &[FreeRoomsSlot], timezone: Tz) -> String {
+    // the slots are cut at the start and end of the events, there are none without events
+    if slots.is_empty() {
+        return "Aucun cours, toutes les salles sont libres".to_string();
+    }
+
+    let rows: Vec<Vec<String>> = slots
+        .iter()
+        .map(|slot| {
+            vec![
+                site_time(&slot.start, timezone),
+                site_time(&slot.end, timezone),
+                slot.free_rooms.iter().map(|x| &x.short_name).join(", "),
+            ]
+        })
+        .collect();
+
+    table(&["Début", "Fin", "Salles libres"], &rows)
+}
+
+fn free_rooms_at_table(free_rooms: &[FreeRoomNow], timezone: Tz) -> String {
+    if free_rooms.is_empty() {
+        return "Aucune salle libre".to_string();
+    }
+
+    let rows: Vec<Vec<String>> = free_rooms
+        .iter()
+        .map(|x| {
+            vec![
+                x.room.short_name.clone(),
+                x.room.name.clone().unwrap_or_default(),
+                x.free_until
+                    .map_or("-".to_string(), |x| site_time(&x, timezone)),
+                x.free_minutes.map_or("-".to_string(), format_minutes),
+            ]
+        })
+        .collect();
+
+    table(&["Salle", "Nom", "Libre jusqu'à", "Durée"], &rows)
+}
+
+fn zik_table(policy: &ZikPolicy, levels: &[(Interval, ZikLevel)], window: TimeWindow) -> String {
+    let timezone = policy.site().timezone();
+    let rows: Vec<Vec<String>> = levels
+        .iter()
+        .map(|(slot, level)| {
+            vec![
+                site_time(&slot.start, timezone),
+                site_time(&slot.end, timezone),
+                level.allowed_activities.description().to_string(),
+                level.exam_names(),
+            ]
+        })
+        .collect();
+
+    // like the slots of the free rooms, the levels only cover the events
+    let slots: Vec<Interval> = levels.iter().map(|(slot, _)| *slot).collect();
+    let default = format!(
+        "Le reste du temps : {}",
+        policy.default_level().description()
+    );
+
+    match (
+        rows.is_empty(),
+        subtract(&window.interval(timezone), &slots).is_empty(),
+    ) {
+        (true, _) => default,
+        (false, true) => table(&["Début", "Fin", "Niveau", "Examens"], &rows),
+        (false, false) => format!(
+            "{}\n\n{default}",
+            table(&["Début", "Fin", "Niveau", "Examens"], &rows)
+        ),
+    }
+}
+
+async fn free_rooms_report(data: &AppData, args: &FreeArgs) -> Result<String, Box<dyn Error>> {
+    let site = data.registry.site_or_default(args.site.as_deref())?;
+    let timezone = site.timezone();
+    let rooms = parse_rooms(&site, args.rooms.as_deref())?;
+    let day = parse_day(&args.day, site.today())?;
+    let window = window(day, 1)?;
+
+    if let Some(at) = &args.at {
+        let at = parse_time(at, day, timezone)?;
+        let (free_rooms, notice) =
+            get_rooms_free_at(data.store.clone(), site, rooms, window, at).await;
+
+        return Ok(with_notice(
+            free_rooms_at_table(&free_rooms, timezone),
+            &notice,
+        ));
+    }
+
+    // the same slots as the free rooms calendar
+    let schedules = get_room_schedules(data.store.clone(), site, rooms, window).await;
+
+    Ok(with_notice(
+        free_slots_table(&compute_free_rooms_slots(&schedules), timezone),
+        &DataNotice::from_schedules(&schedules),
+    ))
+}
+
+async fn zik_report(data: &AppData, args: &ZikArgs) -> Result<String, Box<dyn Error>> {
+    let day = parse_day(&args.day, data.zik_policy.site().today())?;
+    let window = window(day, args.days)?;
+
+    let (schedules, notice) = get_zik_schedules(data.store.clone(), &data.zik_policy, window).await;
+    let levels = compute_zik_levels(&data.zik_policy, &schedules);
+
+    Ok(with_notice(
+        zik_table(&data.zik_policy, &levels, window),
+        &notice,
+    ))
+}
+
+pub async fn print_free_rooms(data: &AppData, args: &FreeArgs) -> Result<(), Box<dyn Error>> {
+    println!("{}", free_rooms_report(data, args).await?);
+    Ok(())
+}
+
+pub async fn print_zik_levels(data: &AppData, args: &ZikArgs) -> Result<(), Box<dyn Error>> {
+    println!("{}", zik_report(data, args).await?);
+    Ok(())
+}
+
+pub async fn export_calendar(data: &AppData, args: &ExportArgs) -> Result<(), Box<dyn Error>> {
+    // the Zik is always at the site of its policy
+    let site = match args.calendar {
+        ExportedCalendar::FreeRooms => data.registry.site_or_default(args.site.as_deref())?,
+        ExportedCalendar::Zik => data.zik_policy.site(),
+    };
+    let day = parse_day(&args.day, site.today())?;
+    let window = window(day, args.days)?;
+
+    let content = match (args.calendar, args.format) {
+        (ExportedCalendar::FreeRooms, format) => {
+            let rooms = parse_rooms(&site, args.rooms.as_deref())?;
+
+            match format {
+                ExportFormat::Ics => {
+                    get_free_rooms_calendar(data.store.clone(), site, rooms, window)
+                        .await
+                        .to_string()
+                }
+                ExportFormat::Json => {
+                    let (slots, notice) =
+                        get_free_rooms_slots(data.store.clone(), site, rooms, window).await;
+                    serde_json::to_string(&FreeRoomsSlots { slots, notice })?
+                }
+            }
+        }
+        (ExportedCalendar::Zik, ExportFormat::Ics) => {
+            get_zik_calendar(data.store.clone(), data.zik_policy.clone(), window)
+                .await
+                .to_string()
+        }
+        (ExportedCalendar::Zik, ExportFormat::Json) => {
+            return Err("The JSON format is only available for the free rooms calendar")?
+        }
+    };
+
+    fs::write(&args.out, content)
+        .map_err(|e| format!("couldn't write {}: {e}", args.out.display()))?;
+    tracing::info!("Wrote the calendar to {}", args.out.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        caching::cal_caching::{CacheConfig, CalendarCache},
+        calendar_parsing::rooms::RoomRegistry,
+        cli_params::arg_parsing::{Cli, Command},
+        networking::calendar_source::{CalendarStore, DirectorySource},
+    };
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    // the calendars of tests/fixtures: TD06 is busy from 08:00 to 10:00 (UTC) on 2024-10-21,
+    // TD15 from 09:00 to 12:00
+    fn fixture_data() -> AppData {
+        let registry = RoomRegistry::from_toml(include_str!("../rooms.toml")).unwrap();
+        let zik_policy = ZikPolicy::from_toml(include_str!("../zik.toml"), &registry).unwrap();
+
+        AppData {
+            registry: Arc::new(registry),
+            zik_policy: Arc::new(zik_policy),
+            store: Arc::new(CalendarStore::new(
+                DirectorySource::new(PathBuf::from("tests/fixtures")),
+                CalendarCache::in_memory(CacheConfig::default()),
+            )),
+        }
+    }
+
+    fn parse_command(args: &[&str]) -> Command {
+        Cli::try_parse_from([&["ade"], args].concat())
+            .unwrap()
+            .command
+            .unwrap()
+    }
+
+    #[test]
+    fn days_and_rooms_are_parsed() {
+        let today = NaiveDate::from_ymd_opt(2024, 10, 21).unwrap();
+        assert_eq!(parse_day("today", today), Ok(today));
+        assert_eq!(
+            parse_day("Tomorrow", today),
+            Ok(NaiveDate::from_ymd_opt(2024, 10, 22).unwrap())
+        );
+        assert_eq!(
+            parse_day("2024-11-04", today),
+            Ok(NaiveDate::from_ymd_opt(2024, 11, 4).unwrap())
+        );
+        assert!(parse_day("someday", today).is_err());
+
+        let data = fixture_data();
+        let site = data.registry.default_site();
+        let rooms = parse_rooms(&site, Some("7, 6,td6")).unwrap();
+        let names: Vec<String> = rooms.iter().map(|x| x.short_name()).collect();
+        assert_eq!(names, ["TD06", "TD07"]);
+        assert!(parse_rooms(&site, Some("6,TD99")).is_err());
+    }
+
+    #[test]
+    fn tables_are_aligned() {
+        let rows = vec![
+            vec!["TD06".to_string(), "1h30".to_string()],
+            vec!["Amphi A".to_string(), "45min".to_string()],
+        ];
+
+        assert_eq!(
+            table(&["Salle", "Durée"], &rows),
+            "Salle    Durée\n-------  -----\nTD06     1h30\nAmphi A  45min"
+        );
+        assert_eq!(format_minutes(90), "1h30");
+        assert_eq!(format_minutes(120), "2h");
+    }
+
+    #[tokio::test]
+    async fn free_rooms_are_listed_per_slot_or_at_a_time() {
+        let data = fixture_data();
+        let Command::Free(args) =
+            parse_command(&["free", "--rooms", "6,15", "--day", "2024-10-21"])
+        else {
+            panic!("not the free subcommand");
+        };
+        let report = free_rooms_report(&data, &args).await.unwrap();
+
+        // 08:00-09:00: TD15, 09:00-10:00: none, 10:00-12:00: TD06
+        let lines: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("TD15"));
+        // in the timezone of the site (UTC+2 in October), whatever the host's
+        assert!(lines[1].starts_with("21/10 11:00  21/10 12:00"));
+        assert!(lines[2].ends_with("TD06"));
+
+        // 09:30 UTC
+        let Command::Free(args) = parse_command(&[
+            "free",
+            "--rooms",
+            "6,7,15",
+            "--day",
+            "2024-10-21",
+            "--at",
+            "11:30",
+        ]) else {
+            panic!("not the free subcommand");
+        };
+        let report = free_rooms_report(&data, &args).await.unwrap();
+
+        // TD07 has no calendar in the fixtures
+        assert_eq!(
+            report,
+            format!("Aucune salle libre\n\nDonnées indisponibles : TD07")
+        );
+    }
+}